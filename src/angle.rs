@@ -0,0 +1,75 @@
+use std::f64::consts;
+use std::fmt;
+
+/// The unit that trigonometric operations measure angles in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AngleMode {
+    Deg, // a full turn is 360
+    #[default]
+    Rad, // a full turn is 2π
+    Grad, // a full turn is 400
+}
+
+impl AngleMode {
+    /// How many radians a single unit of this mode is
+    pub fn radians(self) -> f64 {
+        match self {
+            AngleMode::Deg => consts::PI / 180.0,
+            AngleMode::Rad => 1.0,
+            AngleMode::Grad => consts::PI / 200.0,
+        }
+    }
+
+    /// Converts an angle in this unit to radians
+    pub fn to_radians(self, angle: f64) -> f64 {
+        match self {
+            AngleMode::Deg => angle.to_radians(),
+            AngleMode::Rad => angle,
+            AngleMode::Grad => angle * self.radians(),
+        }
+    }
+
+    /// Converts an angle in radians to this unit
+    pub fn from_radians(self, angle: f64) -> f64 {
+        match self {
+            AngleMode::Deg => angle.to_degrees(),
+            AngleMode::Rad => angle,
+            AngleMode::Grad => angle / self.radians(),
+        }
+    }
+}
+
+impl fmt::Display for AngleMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            AngleMode::Deg => "deg",
+            AngleMode::Rad => "rad",
+            AngleMode::Grad => "grad",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn converts_to_and_from_radians() {
+        assert!(close(AngleMode::Deg.to_radians(180.0), consts::PI));
+        assert!(close(AngleMode::Grad.to_radians(200.0), consts::PI));
+        assert!(close(AngleMode::Rad.to_radians(1.5), 1.5));
+        assert!(close(AngleMode::Deg.from_radians(consts::FRAC_PI_2), 90.0));
+        assert!(close(AngleMode::Grad.from_radians(consts::FRAC_PI_2), 100.0));
+    }
+
+    #[test]
+    fn defaults_to_radians() {
+        assert_eq!(AngleMode::default(), AngleMode::Rad);
+        assert_eq!(AngleMode::Grad.to_string(), "grad");
+    }
+}
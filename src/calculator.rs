@@ -0,0 +1,1552 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::f64::consts;
+use std::mem;
+use std::ops::{Add, Sub, Mul, Neg};
+
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use angle::AngleMode;
+use error::CalcError;
+use format::{DisplayFormat, Radix};
+use infix;
+use integer::{self, MachineWord};
+use list;
+use matrix;
+use op::StackOp;
+use parser::{self, ParseError};
+use stats::{LinearFit, Statistic};
+use unit;
+use value::{NumMode, Value, DEFAULT_PRECISION, MAX_PRECISION};
+
+// We need a VecDeque because we need to also push to the back.
+// Using a regular vec would require dissolving the entire stack, 
+// just to push one element, and then add everything back.
+// This is more efficient.
+// A LinkedList could also be used, but the VecDeque has better locality.
+pub type Stack = VecDeque<Value>;
+
+/// The named variables, kept apart from the stack
+type Vars = BTreeMap<String, Value>;
+
+/// The settings that change how operations are carried out
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Settings {
+    mode: NumMode,         // how numbers are entered
+    precision: u64,        // significant digits kept when dividing decimals
+    word: MachineWord,     // the words bitwise operations work on
+    radix: Radix,          // the base integers are displayed in
+    angle: AngleMode,      // the unit trigonometric operations measure angles in
+    format: DisplayFormat, // how numbers are displayed
+    levels: Option<usize>, // how many levels of the stack are displayed, if not all of them
+}
+
+/// What an operation can change, as remembered for undo
+#[derive(Debug, Clone)]
+struct Snapshot {
+    stack: Stack,
+    vars: Vars,
+    fit: Option<LinearFit>,
+    settings: Settings,
+}
+
+/// How many snapshots of the stack are kept for undo by default
+pub const DEFAULT_HISTORY_DEPTH: usize = 100;
+
+/// A user-defined word, such as "hypot" in ": hypot dup * swap dup * + sqrt ;"
+#[derive(Debug, Clone, Default)]
+struct Word {
+    source: Vec<String>, // the body as it was typed
+    ops: Vec<StackOp>,   // the body with every word expanded into built-in operations
+}
+
+/// A word whose definition has been started with ":" but not yet ended with ";"
+#[derive(Debug, Clone, Default)]
+struct Definition {
+    name: Option<String>,
+    word: Word,
+}
+
+/// The calculator engine, which owns the stack and applies operations to it
+#[derive(Debug, Clone)]
+pub struct Calculator {
+    stack: Stack,
+    undo_history: VecDeque<Snapshot>, // snapshots taken before each operation, newest at the back
+    redo_history: Vec<Snapshot>,      // snapshots of undone states, newest at the back
+    history_depth: usize,
+    words: BTreeMap<String, Word>,
+    definition: Option<Definition>,
+    vars: Vars,
+    fit: Option<LinearFit>, // the line fitted by the last linreg
+    settings: Settings,
+}
+
+impl Default for Calculator {
+    fn default() -> Self {
+        Self::with_history_depth(DEFAULT_HISTORY_DEPTH)
+    }
+}
+
+impl Calculator {
+    /// Creates a calculator with an empty stack
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a calculator that remembers at most `depth` operations for undo
+    pub fn with_history_depth(depth: usize) -> Self {
+        Calculator {
+            stack: Stack::new(),
+            undo_history: VecDeque::new(),
+            redo_history: Vec::new(),
+            history_depth: depth,
+            words: BTreeMap::new(),
+            definition: None,
+            vars: Vars::new(),
+            fit: None,
+            settings: Settings {
+                mode: NumMode::Float,
+                precision: DEFAULT_PRECISION,
+                word: MachineWord::default(),
+                radix: Radix::default(),
+                angle: AngleMode::default(),
+                format: DisplayFormat::default(),
+                levels: None,
+            },
+        }
+    }
+
+    /// Changes how many operations can be undone,
+    /// forgetting the oldest ones if the history is already deeper than that
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history_depth = depth;
+        self.trim_history();
+    }
+
+    /// Read access to the stack; the top of the stack is at the back
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    /// How numbers are entered
+    pub fn mode(&self) -> NumMode {
+        self.settings.mode
+    }
+
+    /// Changes how numbers are entered from now on.
+    /// The numbers already on the stack keep their representation.
+    pub fn set_mode(&mut self, mode: NumMode) {
+        self.settings.mode = mode;
+    }
+
+    /// The number of significant digits kept when dividing decimals
+    pub fn precision(&self) -> u64 {
+        self.settings.precision
+    }
+
+    /// Changes the number of significant digits kept when dividing decimals,
+    /// which is at least 1 and at most MAX_PRECISION
+    pub fn set_precision(&mut self, precision: u64) {
+        self.settings.precision = precision.clamp(1, MAX_PRECISION);
+    }
+
+    /// The base integers are displayed in
+    pub fn radix(&self) -> Radix {
+        self.settings.radix
+    }
+
+    /// Changes the base integers are displayed in
+    pub fn set_radix(&mut self, radix: Radix) {
+        self.settings.radix = radix;
+    }
+
+    /// The size and signedness of the words bitwise operations work on
+    pub fn word(&self) -> MachineWord {
+        self.settings.word
+    }
+
+    /// Changes the size and signedness of the words bitwise operations work on
+    pub fn set_word(&mut self, word: MachineWord) {
+        self.settings.word = word;
+    }
+
+    /// The unit trigonometric operations measure angles in
+    pub fn angle(&self) -> AngleMode {
+        self.settings.angle
+    }
+
+    /// Changes the unit trigonometric operations measure angles in
+    pub fn set_angle(&mut self, angle: AngleMode) {
+        self.settings.angle = angle;
+    }
+
+    /// How numbers are displayed
+    pub fn format(&self) -> DisplayFormat {
+        self.settings.format
+    }
+
+    /// Changes how numbers are displayed, with at most MAX_DIGITS digits.
+    /// The numbers on the stack keep their full precision either way.
+    pub fn set_format(&mut self, format: DisplayFormat) {
+        self.settings.format = format.clamped();
+    }
+
+    /// How many levels from the top of the stack are displayed, or None for all of them
+    pub fn levels(&self) -> Option<usize> {
+        self.settings.levels
+    }
+
+    /// Changes how many levels from the top of the stack are displayed
+    pub fn set_levels(&mut self, levels: Option<usize>) {
+        self.settings.levels = levels;
+    }
+
+    /// Pushes a number onto the stack
+    pub fn push<V: Into<Value>>(&mut self, num: V) {
+        let snapshot = self.snapshot();
+        self.stack.push_back(num.into());
+        self.record(snapshot);
+    }
+
+    /// Applies a single operation to the stack.
+    /// If the operation fails, the stack is left untouched.
+    pub fn apply(&mut self, op: StackOp) -> Result<(), CalcError> {
+        match op {
+            StackOp::Undo => self.undo(),
+            StackOp::Redo => self.redo(),
+            op => {
+                let snapshot = self.snapshot();
+                eval(&mut self.stack, &mut self.vars, &mut self.fit, &mut self.settings, op)?;
+                self.record(snapshot);
+                Ok(())
+            }
+        }
+    }
+
+    /// Restores the stack, the variables and the settings to how they were before the last operation
+    pub fn undo(&mut self) -> Result<(), CalcError> {
+        let previous = self.undo_history.pop_back().ok_or(CalcError::NothingToUndo)?;
+        let current = self.restore(previous);
+        self.redo_history.push(current);
+        Ok(())
+    }
+
+    /// Reapplies the last undone operation
+    pub fn redo(&mut self) -> Result<(), CalcError> {
+        let next = self.redo_history.pop().ok_or(CalcError::NothingToRedo)?;
+        let current = self.restore(next);
+        self.undo_history.push_back(current);
+        self.trim_history();
+        Ok(())
+    }
+
+    /// Takes a snapshot of everything an operation can change
+    fn snapshot(&self) -> Snapshot {
+        Snapshot { stack: self.stack.clone(), vars: self.vars.clone(), fit: self.fit.clone(), settings: self.settings }
+    }
+
+    /// Goes back to a snapshot, returning one of the current state
+    fn restore(&mut self, snapshot: Snapshot) -> Snapshot {
+        Snapshot {
+            stack: mem::replace(&mut self.stack, snapshot.stack),
+            vars: mem::replace(&mut self.vars, snapshot.vars),
+            fit: mem::replace(&mut self.fit, snapshot.fit),
+            settings: mem::replace(&mut self.settings, snapshot.settings),
+        }
+    }
+
+    /// Remembers how things looked before a change.
+    /// Any new change makes the undone operations unreachable, so they're dropped.
+    fn record(&mut self, snapshot: Snapshot) {
+        self.undo_history.push_back(snapshot);
+        self.redo_history.clear();
+        self.trim_history();
+    }
+
+    /// Forgets the oldest snapshots beyond the history depth
+    fn trim_history(&mut self) {
+        while self.undo_history.len() > self.history_depth {
+            self.undo_history.pop_front();
+        }
+    }
+
+    /// Whether a word definition has been started but not yet ended
+    pub fn is_defining(&self) -> bool {
+        self.definition.is_some()
+    }
+
+    /// Lists the user-defined words in alphabetical order, along with their bodies
+    pub fn words(&self) -> impl Iterator<Item = (&str, String)> + '_ {
+        self.words
+            .iter()
+            .map(|(name, word)| (name.as_str(), word.source.join(" ")))
+    }
+
+    /// Lists the named variables in alphabetical order, along with their values
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &Value)> + '_ {
+        self.vars.iter().map(|(name, num)| (name.as_str(), num))
+    }
+
+    /// Tokenizes and evaluates a whole line, such as "2 3 +",
+    /// or an infix expression if the line starts with "=", such as "= 2 + 3".
+    /// Evaluation stops at the first token that can't be parsed or applied,
+    /// leaving the effects of the preceding tokens in place.
+    pub fn eval_str(&mut self, input: &str) -> Result<(), CalcError> {
+        if let Some(expr) = input.trim_start().strip_prefix('=') {
+            return self.eval_infix(expr);
+        }
+
+        for token in parser::tokenize(input) {
+            self.eval_token(&token)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates a single token, which is either a user-defined word,
+    /// a built-in operation, or part of a word definition.
+    /// A user-defined word is applied as a whole, so it's undone in one step,
+    /// and leaves the stack untouched if any part of it fails.
+    pub fn eval_token(&mut self, token: &str) -> Result<(), CalcError> {
+        if self.definition.is_some() {
+            return self.compile(token);
+        }
+
+        if token == ":" {
+            self.definition = Some(Definition::default());
+            return Ok(());
+        }
+
+        let ops = self.lookup(token)?;
+        self.apply_all(ops)
+    }
+
+    /// Evaluates an infix expression, such as "(2 + 3) * sin(pi / 4)", by converting it to RPN.
+    /// Every token is applied to exactly the arguments it's given in the expression, on a stack of its own,
+    /// and has to give back a single number, so the expression can't reach into the user's stack.
+    /// The result is pushed onto the stack, so it can be mixed with RPN.
+    pub fn eval_infix(&mut self, expr: &str) -> Result<(), CalcError> {
+        let mut vars = self.vars.clone();
+        let mut fit = self.fit.clone();
+        let mut settings = self.settings;
+        let mut values = Stack::new();
+
+        for step in infix::translate(expr)? {
+            let ops = self.lookup(&step.token)?;
+            if ops.iter().any(|op| matches!(*op, StackOp::Undo | StackOp::Redo)) {
+                return Err(CalcError::InvalidExpression("undo and redo can't be used in an expression".to_string()));
+            }
+
+            let wrong_arguments = || {
+                CalcError::InvalidExpression(format!("{} can't take {} argument(s)", step.token, step.arity))
+            };
+            let start = values.len().checked_sub(step.arity).ok_or_else(wrong_arguments)?;
+            let mut args = values.split_off(start);
+            for op in ops {
+                eval(&mut args, &mut vars, &mut fit, &mut settings, op).map_err(|err| match err {
+                    CalcError::StackUnderflow { .. } => wrong_arguments(),
+                    err => err,
+                })?;
+            }
+            if args.len() != 1 {
+                return Err(wrong_arguments());
+            }
+            values.extend(args);
+        }
+
+        let result = values.pop_back().ok_or_else(|| CalcError::InvalidExpression("nothing to evaluate".to_string()))?;
+        self.vars = vars;
+        self.fit = fit;
+        self.settings = settings;
+        self.push(result);
+        Ok(())
+    }
+
+    /// Applies a sequence of operations as a whole,
+    /// so it's undone in one step and leaves the stack untouched if any part of it fails
+    fn apply_all(&mut self, mut ops: Vec<StackOp>) -> Result<(), CalcError> {
+        if ops.len() == 1 {
+            return self.apply(ops.remove(0));
+        }
+
+        let snapshot = self.snapshot();
+        for op in ops {
+            if let Err(err) = eval(&mut self.stack, &mut self.vars, &mut self.fit, &mut self.settings, op) {
+                self.restore(snapshot);
+                return Err(err);
+            }
+        }
+        self.record(snapshot);
+        Ok(())
+    }
+
+    /// Finds the operations a token stands for, looking at the user-defined words first.
+    /// Then a token naming a variable recalls it, so variables hide constants of the same name.
+    fn lookup(&self, token: &str) -> Result<Vec<StackOp>, CalcError> {
+        if let Some(word) = self.words.get(token) {
+            return Ok(word.ops.clone());
+        }
+        if self.vars.contains_key(token) {
+            return Ok(vec![StackOp::Recall(token.to_string())]);
+        }
+
+        match parser::parse_string(token, self.settings.mode) {
+            Some(op) => Ok(vec![op]),
+            None => Err(ParseError { token: token.to_string() }.into()),
+        }
+    }
+
+    /// Adds a token to the word being defined, or finishes it on ";".
+    /// Words used in the body are expanded right away, so redefining them later
+    /// doesn't change the meaning of this word.
+    /// Any error abandons the definition.
+    fn compile(&mut self, token: &str) -> Result<(), CalcError> {
+        let mut definition = self.definition.take().expect("compile is only called while defining");
+
+        if token == ";" {
+            let name = definition.name.ok_or_else(|| invalid_definition("the word needs a name"))?;
+            self.words.insert(name, definition.word);
+            return Ok(());
+        }
+
+        if definition.name.is_none() {
+            if !parser::is_word_name(token) {
+                return Err(invalid_definition(&format!("{} can't be used as a name", token)));
+            }
+            definition.name = Some(token.to_string());
+        } else {
+            let ops = self.lookup(token)?;
+            if ops.iter().any(|op| matches!(*op, StackOp::Undo | StackOp::Redo)) {
+                return Err(invalid_definition("undo and redo can't be used inside a word"));
+            }
+            definition.word.ops.extend(ops);
+            definition.word.source.push(token.to_string());
+        }
+
+        self.definition = Some(definition);
+        Ok(())
+    }
+}
+
+/// Shorthand for rejecting a word definition
+fn invalid_definition(reason: &str) -> CalcError {
+    CalcError::InvalidDefinition(reason.to_string())
+}
+
+/// Ensures the stack holds at least `needed` elements
+fn require(stack: &Stack, needed: usize) -> Result<(), CalcError> {
+    if stack.len() >= needed {
+        Ok(())
+    } else {
+        Err(CalcError::StackUnderflow { needed, available: stack.len() })
+    }
+}
+
+/// Applies a binary operation if the stack has enough elements.
+/// If the result is NaN while neither operand was, the operation
+/// is reported as undefined for the first operand.
+/// NB The top of the stack holds the SECOND operator, not the first
+/// So if we push 2 1 - the operation becomes 2 - 1, not 1 - 2
+fn eval_binop<F>(stack: &mut Stack, name: &'static str, fun: F) -> Result<(), CalcError>
+where
+    F: FnOnce(Value, Value) -> Value,
+{
+    eval_binop_fitting(stack, name, fit, fun)
+}
+
+/// Applies a binary operation like `eval_binop`, with `fit` checking which operands it can combine
+fn eval_binop_fitting<F>(
+    stack: &mut Stack,
+    name: &'static str,
+    fit: Fit,
+    fun: F,
+) -> Result<(), CalcError>
+where
+    F: FnOnce(Value, Value) -> Value,
+{
+    require(stack, 2)?;
+    let len = stack.len();
+    let (a, b) = (stack[len - 1].clone(), stack[len - 2].clone());
+    fit(name, &b, &a)?;
+    let nan_operand = a.is_nan() || b.is_nan();
+    let result = fun(b.clone(), a);
+
+    if result.is_nan() && !nan_operand {
+        return Err(CalcError::DomainError { op: name, value: b });
+    }
+
+    stack.truncate(len - 2);
+    stack.push_back(result);
+    Ok(())
+}
+
+/// Checks whether two operands can be combined, given the name of the operation combining them
+type Fit = fn(&'static str, &Value, &Value) -> Result<(), CalcError>;
+
+/// Checks that two operands can be combined element-wise
+fn fit(name: &'static str, a: &Value, b: &Value) -> Result<(), CalcError> {
+    if !a.fits(b) {
+        return Err(CalcError::DimensionMismatch { op: name, left: a.shape(), right: b.shape() });
+    }
+    Ok(())
+}
+
+/// Checks that two operands can be multiplied, as matrices if need be
+fn fit_product(name: &'static str, a: &Value, b: &Value) -> Result<(), CalcError> {
+    if !a.fits_product(b) {
+        return Err(CalcError::DimensionMismatch { op: name, left: a.shape(), right: b.shape() });
+    }
+    Ok(())
+}
+
+/// Checks that two operands can be added up, which also needs them to measure the same kind of thing
+fn fit_sum(name: &'static str, a: &Value, b: &Value) -> Result<(), CalcError> {
+    fit(name, a, b)?;
+    if !unit::compatible(a, b) {
+        return Err(CalcError::IncompatibleUnits { op: name, left: a.unit(), right: b.unit() });
+    }
+    Ok(())
+}
+
+/// Divides the second element by the topmost one, refusing to divide by zero
+fn divide(stack: &mut Stack, mode: NumMode, precision: u64) -> Result<(), CalcError> {
+    require(stack, 2)?;
+    if stack[stack.len() - 1].is_zero() {
+        return Err(CalcError::DivisionByZero);
+    }
+    eval_binop(stack, "/", |a, b| {
+        let b = unit::align(&a, b, mode, precision);
+        a.div(b, mode, precision)
+    })
+}
+
+/// Applies a binary operation that only works on whole numbers, element-wise on lists.
+/// Other numbers are reported as outside the operation's domain.
+fn eval_int_binop<F>(stack: &mut Stack, name: &'static str, fun: F) -> Result<(), CalcError>
+where
+    F: Fn(BigInt, BigInt) -> Result<BigInt, CalcError>,
+{
+    require(stack, 2)?;
+    let len = stack.len();
+    let (a, b) = (stack[len - 2].clone(), stack[len - 1].clone());
+    let result = zip_with(name, a, b, &|a, b| {
+        let b = to_integer(name, &b)?;
+        let a = to_integer(name, &a)?;
+        Ok(Value::Int(fun(a, b)?))
+    })?;
+
+    stack.truncate(len - 2);
+    stack.push_back(result);
+    Ok(())
+}
+
+/// Applies a unary operation that only works on whole numbers, element-wise on lists.
+/// Other numbers are reported as outside the operation's domain.
+fn eval_int_unop<F>(stack: &mut Stack, name: &'static str, fun: F) -> Result<(), CalcError>
+where
+    F: Fn(BigInt) -> Result<BigInt, CalcError>,
+{
+    require(stack, 1)?;
+    let a = stack[stack.len() - 1].clone();
+    let result = map_with(a, &|a| Ok(Value::Int(fun(to_integer(name, &a)?)?)))?;
+
+    stack.pop_back();
+    stack.push_back(result);
+    Ok(())
+}
+
+/// Applies an operation that may fail to every pair of elements if either operand is a list,
+/// pairing a single number with every element of the list, like the arithmetic operators do
+fn zip_with<F>(name: &'static str, a: Value, b: Value, fun: &F) -> Result<Value, CalcError>
+where
+    F: Fn(Value, Value) -> Result<Value, CalcError>,
+{
+    if !a.fits(&b) {
+        return Err(CalcError::DimensionMismatch { op: name, left: a.shape(), right: b.shape() });
+    }
+
+    match (a, b) {
+        (Value::List(xs), Value::List(ys)) => xs.into_iter().zip(ys).map(|(x, y)| fun(x, y)).collect::<Result<_, _>>(),
+        (Value::List(xs), b) => xs.into_iter().map(|x| fun(x, b.clone())).collect::<Result<_, _>>(),
+        (a, Value::List(ys)) => ys.into_iter().map(|y| fun(a.clone(), y)).collect::<Result<_, _>>(),
+        (a, b) => return fun(a, b),
+    }
+    .map(Value::List)
+}
+
+/// Applies an operation that may fail to every element of a list, or to the value itself if it isn't one
+fn map_with<F>(a: Value, fun: &F) -> Result<Value, CalcError>
+where
+    F: Fn(Value) -> Result<Value, CalcError>,
+{
+    match a {
+        Value::List(xs) => xs.into_iter().map(fun).collect::<Result<_, _>>().map(Value::List),
+        a => fun(a),
+    }
+}
+
+/// Converts an operand of an integer operation, which must be a whole number
+fn to_integer(name: &'static str, value: &Value) -> Result<BigInt, CalcError> {
+    value.to_integer().ok_or_else(|| CalcError::DomainError { op: name, value: value.clone() })
+}
+
+/// Checks how far to shift or rotate, which can't be negative
+fn shift_count(name: &'static str, n: BigInt) -> Result<u32, CalcError> {
+    // anything beyond u32 shifts every bit out of the word anyway
+    match n.to_u32() {
+        Some(count) => Ok(count),
+        None if n.is_positive() => Ok(u32::MAX),
+        None => Err(CalcError::DomainError { op: name, value: Value::Int(n) }),
+    }
+}
+
+/// Multiplies every number from 1 up to n, which can't be negative,
+/// nor so big the result would take too long to work out
+fn factorial(n: BigInt) -> Result<BigInt, CalcError> {
+    match n.to_u32() {
+        Some(n) if integer::factorial_fits(n) => Ok(integer::factorial(n)),
+        _ => Err(CalcError::DomainError { op: "fact", value: Value::Int(n) }),
+    }
+}
+
+/// Gives 1 if n is a prime and 0 otherwise, unless n is so big the test would take too long
+fn is_prime(n: BigInt) -> Result<BigInt, CalcError> {
+    if n.bits() > integer::MAX_PRIME_BITS {
+        return Err(CalcError::DomainError { op: "isprime", value: Value::Int(n) });
+    }
+    Ok(BigInt::from(integer::is_prime(&n) as u8))
+}
+
+/// Applies a unary operation if the stack has enough elements, element-wise on lists.
+/// If the result is NaN while the operand wasn't,
+/// the operation is reported as undefined for that operand.
+fn eval_unop<F>(stack: &mut Stack, name: &'static str, fun: F) -> Result<(), CalcError>
+where
+    F: Fn(Value) -> Value,
+{
+    require(stack, 1)?;
+    let a = stack[stack.len() - 1].clone();
+    let result = a.clone().map(&fun);
+
+    if result.is_nan() && !a.is_nan() {
+        return Err(CalcError::DomainError { op: name, value: a });
+    }
+
+    stack.pop_back();
+    stack.push_back(result);
+    Ok(())
+}
+
+/// Applies a unary operation that works on floats, so exact numbers are converted first.
+/// Complex numbers, and real numbers the operation isn't defined for, such as sqrt of -1,
+/// go through its complex counterpart instead.
+fn eval_float_unop<F, G>(stack: &mut Stack, name: &'static str, fun: F, complex_fun: G) -> Result<(), CalcError>
+where
+    F: Fn(f64) -> f64,
+    G: Fn(Complex64) -> Complex64,
+{
+    eval_unop(stack, name, |a| {
+        if let Value::Complex(z) = a {
+            return Value::from_complex(complex_fun(z));
+        }
+
+        let x = a.to_f64();
+        let result = fun(x);
+        if result.is_nan() && !x.is_nan() {
+            Value::from_complex(complex_fun(Complex64::new(x, 0.0)))
+        } else {
+            Value::Float(result)
+        }
+    })
+}
+
+/// Applies a trigonometric function to an angle measured in the given unit
+fn eval_trig(
+    stack: &mut Stack,
+    name: &'static str,
+    angle: AngleMode,
+    fun: fn(f64) -> f64,
+    complex_fun: fn(Complex64) -> Complex64,
+) -> Result<(), CalcError> {
+    let unit = angle.radians();
+    eval_float_unop(stack, name, |x| fun(angle.to_radians(x)), |z| complex_fun(z.scale(unit)))
+}
+
+/// Applies an inverse trigonometric function, giving an angle measured in the given unit
+fn eval_inverse_trig(
+    stack: &mut Stack,
+    name: &'static str,
+    angle: AngleMode,
+    fun: fn(f64) -> f64,
+    complex_fun: fn(Complex64) -> Complex64,
+) -> Result<(), CalcError> {
+    let unit = angle.radians();
+    eval_float_unop(stack, name, |x| angle.from_radians(fun(x)), |z| complex_fun(z).unscale(unit))
+}
+
+/// Replaces the topmost complex number with its length and angle
+fn to_polar(stack: &mut Stack, angle: AngleMode) -> Result<(), CalcError> {
+    require(stack, 1)?;
+    if !stack[stack.len() - 1].is_scalar() {
+        return Err(CalcError::DomainError { op: "->polar", value: stack[stack.len() - 1].clone() });
+    }
+    let (r, theta) = stack.pop_back().unwrap().to_complex().to_polar();
+    stack.push_back(Value::Float(r));
+    stack.push_back(Value::Float(angle.from_radians(theta)));
+    Ok(())
+}
+
+/// Replaces a length and an angle (on top) with the complex number they describe
+fn to_rect(stack: &mut Stack, angle: AngleMode) -> Result<(), CalcError> {
+    eval_binop(stack, "->rect", |r, theta| {
+        Value::from_complex(Complex64::from_polar(r.to_f64(), angle.to_radians(theta.to_f64())))
+    })
+}
+
+/// Converts the topmost element, or every element of a list, to an exact fraction
+fn to_fraction(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 1)?;
+    let top = stack.len() - 1;
+    stack[top] = map_with(stack[top].clone(), &|x| match x.to_rational() {
+        Some(fraction) => Ok(Value::Rational(fraction)),
+        None => Err(CalcError::DomainError { op: "->frac", value: x }),
+    })?;
+    Ok(())
+}
+
+/// Replaces the topmost element with the result of an operation that may fail
+fn eval_try_unop<F>(stack: &mut Stack, fun: F) -> Result<(), CalcError>
+where
+    F: FnOnce(&Value) -> Result<Value, CalcError>,
+{
+    require(stack, 1)?;
+    let top = stack.len() - 1;
+    stack[top] = fun(&stack[top])?;
+    Ok(())
+}
+
+/// Replaces the two topmost elements with the result of an operation that may fail
+fn eval_try_binop<F>(stack: &mut Stack, fun: F) -> Result<(), CalcError>
+where
+    F: FnOnce(Value, Value) -> Result<Value, CalcError>,
+{
+    require(stack, 2)?;
+    let len = stack.len();
+    let result = fun(stack[len - 2].clone(), stack[len - 1].clone())?;
+    stack.truncate(len - 2);
+    stack.push_back(result);
+    Ok(())
+}
+
+/// Replaces the topmost list with its elements, followed by how many there are
+fn explode(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 1)?;
+    let xs = match stack.pop_back().unwrap() {
+        Value::List(xs) => xs,
+        value => {
+            stack.push_back(value.clone());
+            return Err(CalcError::DomainError { op: "explode", value });
+        }
+    };
+    let count = Value::from(xs.len() as i64);
+    stack.extend(xs);
+    stack.push_back(count);
+    Ok(())
+}
+
+/// Gathers the n elements below the count into a list, so "explode collect" changes nothing
+fn collect(stack: &mut Stack) -> Result<(), CalcError> {
+    let n = peek_count(stack, "collect")?;
+    require(stack, n.saturating_add(1))?;
+    stack.pop_back();
+    let xs = stack.split_off(stack.len() - n);
+    if let Some(list) = xs.iter().find(|x| !x.is_scalar()) {
+        let list = list.clone();
+        stack.extend(xs);
+        stack.push_back(Value::from(n as i64));
+        return Err(CalcError::DomainError { op: "collect", value: list });
+    }
+    stack.push_back(Value::List(xs.into_iter().collect()));
+    Ok(())
+}
+
+// Folds the stack over fun from the bottom up, then pushes the result.
+// An empty stack gives `start`, the identity of the operation.
+// The elements have to fit together, as they would for the operator on its own.
+fn eval_stackop<F>(stack: &mut Stack, name: &'static str, start: Value, fit: Fit, fun: F) -> Result<(), CalcError>
+where
+    F: Fn(Value, Value) -> Value,
+{
+    let mut values = stack.iter().cloned();
+    let first = values.next().unwrap_or(start);
+    let result = values.try_fold(first, |acc, x| -> Result<Value, CalcError> {
+        fit(name, &acc, &x)?;
+        Ok(fun(acc, x))
+    })?;
+    stack.clear();
+    stack.push_back(result);
+    Ok(())
+}
+
+/// Removes the topmost element of the stack
+fn pop(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 1)?;
+    stack.pop_back();
+    Ok(())
+}
+
+/// Swaps the two topmost elements of the stack
+fn swap(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 2)?;
+    let len = stack.len();
+    stack.swap(len - 1, len - 2);
+    Ok(())
+}
+
+/// Moves the topmost element to the bottom
+fn rotate(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 1)?;
+    let num = stack.pop_back().unwrap();
+    stack.push_front(num); // Important! This must be the opposite of the pop
+    Ok(())
+}
+
+/// Duplicates the topmost element of the stack
+fn duplicate(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 1)?;
+    let num = stack[stack.len() - 1].clone();
+    stack.push_back(num);
+    Ok(())
+}
+
+/// Copies the element `depth` levels below the top onto the stack,
+/// where 0 is the topmost element itself
+fn copy_to_top(stack: &mut Stack, depth: usize) -> Result<(), CalcError> {
+    require(stack, depth.saturating_add(1))?;
+    let num = stack[stack.len() - 1 - depth].clone();
+    stack.push_back(num);
+    Ok(())
+}
+
+/// Moves the element `depth` levels below the top to the top,
+/// where 0 is the topmost element itself
+fn move_to_top(stack: &mut Stack, depth: usize) -> Result<(), CalcError> {
+    require(stack, depth.saturating_add(1))?;
+    let num = stack.remove(stack.len() - 1 - depth).unwrap();
+    stack.push_back(num);
+    Ok(())
+}
+
+/// Removes the second element, keeping the topmost one
+fn nip(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 2)?;
+    stack.remove(stack.len() - 2);
+    Ok(())
+}
+
+/// Copies the topmost element below the second, so "a b" becomes "b a b"
+fn tuck(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 2)?;
+    let num = stack[stack.len() - 1].clone();
+    stack.insert(stack.len() - 2, num);
+    Ok(())
+}
+
+/// Moves the topmost element below the third, so "a b c" becomes "c a b"
+fn minus_rot(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 3)?;
+    let num = stack.pop_back().unwrap();
+    stack.insert(stack.len() - 2, num);
+    Ok(())
+}
+
+/// Removes the two topmost elements
+fn two_drop(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 2)?;
+    stack.truncate(stack.len() - 2);
+    Ok(())
+}
+
+/// Swaps the two topmost pairs, so "a b c d" becomes "c d a b"
+fn two_swap(stack: &mut Stack) -> Result<(), CalcError> {
+    require(stack, 4)?;
+    move_to_top(stack, 3)?;
+    move_to_top(stack, 3)
+}
+
+/// Reads the count on top of the stack, as taken by pick, roll and dupn.
+/// The count must be a whole number that isn't negative.
+/// It's left on the stack, so nothing changes if the operation fails.
+fn peek_count(stack: &Stack, name: &'static str) -> Result<usize, CalcError> {
+    require(stack, 1)?;
+    let top = &stack[stack.len() - 1];
+    to_integer(name, top)?
+        .to_usize()
+        .ok_or_else(|| CalcError::DomainError { op: name, value: top.clone() })
+}
+
+/// Copies the nth element below the count to the top, so "0 pick" is dup and "1 pick" is over
+fn pick(stack: &mut Stack) -> Result<(), CalcError> {
+    let n = peek_count(stack, "pick")?;
+    require(stack, n.saturating_add(2))?;
+    stack.pop_back();
+    copy_to_top(stack, n)
+}
+
+/// Moves the nth element below the count to the top, so "1 roll" is swap and "2 roll" is rot
+fn roll(stack: &mut Stack) -> Result<(), CalcError> {
+    let n = peek_count(stack, "roll")?;
+    require(stack, n.saturating_add(2))?;
+    stack.pop_back();
+    move_to_top(stack, n)
+}
+
+/// Duplicates the n elements below the count, so "2 dupn" is 2dup
+fn dupn(stack: &mut Stack) -> Result<(), CalcError> {
+    let n = peek_count(stack, "dupn")?;
+    require(stack, n.saturating_add(1))?;
+    stack.pop_back();
+    let len = stack.len();
+    for i in len - n..len {
+        let num = stack[i].clone();
+        stack.push_back(num);
+    }
+    Ok(())
+}
+
+/// Summarises the entire stack with a statistic or, if `top` is set,
+/// the n elements below the count on top of the stack.
+/// Percentiles take the percentage from the top of the stack, below the count if there is one.
+fn eval_statistic(
+    stack: &mut Stack,
+    statistic: Statistic,
+    top: bool,
+    mode: NumMode,
+    precision: u64,
+) -> Result<(), CalcError> {
+    let mut arguments = 0; // the elements above the sample
+    let count = if top {
+        arguments += 1;
+        Some(peek_count(stack, statistic.name())?)
+    } else {
+        None
+    };
+    let percent = if statistic == Statistic::Percentile {
+        arguments += 1;
+        require(stack, arguments)?;
+        Some(stack[stack.len() - arguments].clone())
+    } else {
+        None
+    };
+
+    let available = stack.len().saturating_sub(arguments);
+    let n = count.unwrap_or(available);
+    require(stack, n.saturating_add(arguments))?;
+    let sample: Vec<Value> = stack.iter().skip(available - n).take(n).cloned().collect();
+    let nan_sample = sample.iter().any(Value::is_nan);
+    let result = statistic.compute(sample, percent, mode, precision)?;
+    if result.is_nan() && !nan_sample {
+        let sample = stack.iter().skip(available - n).take(n).cloned().collect();
+        return Err(CalcError::DomainError { op: statistic.name(), value: Value::List(sample) });
+    }
+
+    stack.truncate(available - n);
+    stack.push_back(result);
+    Ok(())
+}
+
+/// Fits a line through the entire stack, read from the bottom as alternating x and y values,
+/// replacing the points with the slope, intercept and r² of the line.
+/// The line is remembered for predict.
+fn linear_regression(
+    stack: &mut Stack,
+    fit: &mut Option<LinearFit>,
+    mode: NumMode,
+    precision: u64,
+) -> Result<(), CalcError> {
+    if stack.len() % 2 == 1 {
+        let x = stack.back().expect("an odd stack isn't empty").clone();
+        return Err(CalcError::UnpairedValue(x));
+    }
+
+    let xs = stack.iter().step_by(2).cloned();
+    let ys = stack.iter().skip(1).step_by(2).cloned();
+    let (line, r_squared) = LinearFit::new(xs.zip(ys).collect(), mode, precision)?;
+
+    stack.clear();
+    stack.push_back(line.slope.clone());
+    stack.push_back(line.intercept.clone());
+    stack.push_back(r_squared);
+    *fit = Some(line);
+    Ok(())
+}
+
+/// Replaces the topmost element, x, with the y of the line fitted by the last linreg
+fn predict(stack: &mut Stack, fit: &Option<LinearFit>, precision: u64) -> Result<(), CalcError> {
+    let line = fit.as_ref().ok_or(CalcError::NoFit)?;
+    eval_unop(stack, "predict", |x| line.predict(x, precision))
+}
+
+/// Pops the topmost element into the named variable
+fn store(stack: &mut Stack, vars: &mut Vars, name: String) -> Result<(), CalcError> {
+    require(stack, 1)?;
+    vars.insert(name, stack.pop_back().unwrap());
+    Ok(())
+}
+
+/// Pushes the value of the named variable onto the stack
+fn recall(stack: &mut Stack, vars: &Vars, name: String) -> Result<(), CalcError> {
+    let num = vars.get(&name).cloned().ok_or(CalcError::UndefinedVariable(name))?;
+    stack.push_back(num);
+    Ok(())
+}
+
+/// Changes the number of bits bitwise operations work on, keeping the signedness
+fn set_word_size(settings: &mut Settings, bits: u32) -> Result<(), CalcError> {
+    let word = MachineWord::new(bits, settings.word.signed());
+    settings.word = word.ok_or(CalcError::DomainError { op: "word", value: Value::from(i64::from(bits)) })?;
+    Ok(())
+}
+
+/// Determines what to do given a StackOp, and applies its effect to the stack.
+/// On failure the stack is left untouched.
+fn eval(
+    stack: &mut Stack,
+    vars: &mut Vars,
+    fit: &mut Option<LinearFit>,
+    settings: &mut Settings,
+    last_op: StackOp,
+) -> Result<(), CalcError> {
+    use op::StackOp::*;
+
+    let Settings { mode, precision, word, angle, .. } = *settings;
+    // combines two operands once the right one is rewritten in the units of the left one
+    let aligned = |fun: fn(Value, Value) -> Value| {
+        move |a: Value, b: Value| {
+            let b = unit::align(&a, b, mode, precision);
+            fun(a, b).round_to(precision)
+        }
+    };
+
+    match last_op {
+        // binary operators
+        Add => eval_binop_fitting(stack, "+", fit_sum, aligned(Value::add)),
+        Sub => eval_binop_fitting(stack, "-", fit_sum, aligned(Value::sub)),
+        Mul => eval_binop_fitting(stack, "*", fit_product, aligned(Value::mul)),
+        Div => divide(stack, mode, precision),
+        Pow => eval_binop(stack, "^", |a, b| a.pow(b, mode, precision)),
+        // unary operators
+        Sqrt  => eval_float_unop(stack, "sqrt", f64::sqrt, Complex64::sqrt),
+        Abs   => eval_unop(stack, "abs", Value::abs),
+        Neg   => eval_unop(stack, "neg", Value::neg),
+        Ln    => eval_float_unop(stack, "ln", f64::ln, Complex64::ln),
+        Lg    => eval_float_unop(stack, "lg", f64::log2, Complex64::log2),
+        Log   => eval_float_unop(stack, "log", f64::log10, Complex64::log10),
+        Sin   => eval_trig(stack, "sin", angle, f64::sin, Complex64::sin),
+        Asin  => eval_inverse_trig(stack, "asin", angle, f64::asin, Complex64::asin),
+        Cos   => eval_trig(stack, "cos", angle, f64::cos, Complex64::cos),
+        Acos  => eval_inverse_trig(stack, "acos", angle, f64::acos, Complex64::acos),
+        Tan   => eval_trig(stack, "tan", angle, f64::tan, Complex64::tan),
+        Atan  => eval_inverse_trig(stack, "atan", angle, f64::atan, Complex64::atan),
+        ToDeg => eval_float_unop(stack, "to deg", f64::to_degrees, |z| z.scale(180.0 / consts::PI)),
+        ToRad => eval_float_unop(stack, "to rad", f64::to_radians, |z| z.scale(consts::PI / 180.0)),
+        ToFrac  => to_fraction(stack),
+        ToFloat => eval_unop(stack, "->float", |a| Value::Float(a.to_f64())),
+        // integer operators
+        IntDiv => eval_int_binop(stack, "div", |a, b| {
+            if b.is_zero() { Err(CalcError::DivisionByZero) } else { Ok(a.div_floor(&b)) }
+        }),
+        Mod => eval_int_binop(stack, "mod", |a, b| {
+            if b.is_zero() { Err(CalcError::DivisionByZero) } else { Ok(a.mod_floor(&b)) }
+        }),
+        Gcd     => eval_int_binop(stack, "gcd", |a, b| Ok(integer::gcd(&a, &b))),
+        Lcm     => eval_int_binop(stack, "lcm", |a, b| Ok(integer::lcm(&a, &b))),
+        Fact    => eval_int_unop(stack, "fact", factorial),
+        IsPrime => eval_int_unop(stack, "isprime", is_prime),
+        // bitwise operators
+        And => eval_int_binop(stack, "and", |a, b| Ok(word.and(&a, &b))),
+        Or  => eval_int_binop(stack, "or", |a, b| Ok(word.or(&a, &b))),
+        Xor => eval_int_binop(stack, "xor", |a, b| Ok(word.xor(&a, &b))),
+        Not => eval_int_unop(stack, "not", |a| Ok(word.not(&a))),
+        Shl => eval_int_binop(stack, "shl", |a, n| Ok(word.shl(&a, shift_count("shl", n)?))),
+        Shr => eval_int_binop(stack, "shr", |a, n| Ok(word.shr(&a, shift_count("shr", n)?))),
+        Rol => eval_int_binop(stack, "rol", |a, n| Ok(word.rol(&a, shift_count("rol", n)?))),
+        Ror => eval_int_binop(stack, "ror", |a, n| Ok(word.ror(&a, shift_count("ror", n)?))),
+        // complex operators
+        Re      => eval_unop(stack, "re", |a| match a { Value::Complex(z) => Value::Float(z.re), a => a }),
+        Im      => eval_unop(stack, "im", |a| Value::Float(a.to_complex().im)),
+        Arg     => eval_unop(stack, "arg", |a| Value::Float(angle.from_radians(a.to_complex().arg()))),
+        Conj    => eval_unop(stack, "conj", |a| match a { Value::Complex(z) => Value::Complex(z.conj()), a => a }),
+        ToPolar => to_polar(stack, angle),
+        ToRect  => to_rect(stack, angle),
+        // list operations
+        Dot     => eval_try_binop(stack, |a, b| list::dot(&a, &b, precision)),
+        Cross   => eval_try_binop(stack, |a, b| list::cross(&a, &b, precision)),
+        Norm    => eval_try_unop(stack, list::norm),
+        Len     => eval_try_unop(stack, list::len),
+        Get     => eval_try_binop(stack, |a, i| list::get(&a, &i)),
+        Concat  => eval_try_binop(stack, list::concat),
+        Explode => explode(stack),
+        Collect => collect(stack),
+        // matrix operations
+        Det       => eval_try_unop(stack, |a| matrix::det(a, mode, precision)),
+        Inv       => eval_try_unop(stack, |a| matrix::inverse(a, mode, precision)),
+        Transpose => eval_try_unop(stack, matrix::transpose),
+        Trace     => eval_try_unop(stack, matrix::trace),
+        Solve     => eval_try_binop(stack, |a, b| matrix::solve(&a, &b, mode, precision)),
+        // units
+        Convert(unit) => eval_try_unop(stack, |a| unit::convert(a, &unit, mode, precision)),
+        // stack operations
+        Sum       => eval_stackop(stack, "sum", Value::from(0), fit_sum, aligned(Value::add)),
+        Prod      => eval_stackop(stack, "prod", Value::from(1), fit_product, aligned(Value::mul)),
+        Pop       => pop(stack),
+        Clear     => { stack.clear(); Ok(()) },
+        Swap      => swap(stack),
+        Rotate    => rotate(stack),
+        Duplicate => duplicate(stack),
+        Over      => copy_to_top(stack, 1),
+        Nip       => nip(stack),
+        Tuck      => tuck(stack),
+        Rot       => move_to_top(stack, 2),
+        MinusRot  => minus_rot(stack),
+        TwoDup    => { require(stack, 2)?; copy_to_top(stack, 1)?; copy_to_top(stack, 1) },
+        TwoDrop   => two_drop(stack),
+        TwoSwap   => two_swap(stack),
+        Pick      => pick(stack),
+        Roll      => roll(stack),
+        Depth     => { let depth = BigInt::from(stack.len()); stack.push_back(Value::Int(depth)); Ok(()) },
+        DupN      => dupn(stack),
+        // statistics
+        Stat(statistic)    => eval_statistic(stack, statistic, false, mode, precision),
+        StatTop(statistic) => eval_statistic(stack, statistic, true, mode, precision),
+        LinReg             => linear_regression(stack, fit, mode, precision),
+        Predict            => predict(stack, fit, precision),
+        // variables
+        Store(name)  => store(stack, vars, name),
+        Recall(name) => recall(stack, vars, name),
+        // number
+        Num(n) => { stack.push_back(n); Ok(()) },
+        // settings
+        SetMode(mode)           => { settings.mode = mode; Ok(()) },
+        SetPrecision(precision) => { settings.precision = precision.clamp(1, MAX_PRECISION); Ok(()) },
+        SetWordSize(bits)       => set_word_size(settings, bits),
+        SetSigned(signed)       => { settings.word = settings.word.with_signed(signed); Ok(()) },
+        SetRadix(radix)         => { settings.radix = radix; Ok(()) },
+        SetAngle(angle)         => { settings.angle = angle; Ok(()) },
+        SetFormat(format)       => { settings.format = format.clamped(); Ok(()) },
+        SetLevels(levels)       => { settings.levels = levels; Ok(()) },
+        // history is kept by the Calculator, not the stack
+        Undo | Redo => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use format::MAX_DIGITS;
+    use unit::Unit;
+
+    /// Evaluates every line of the input, stopping at the first error
+    fn eval_lines(calc: &mut Calculator, input: &str) -> Result<(), CalcError> {
+        input.lines().try_for_each(|line| calc.eval_str(line))
+    }
+
+    /// The stack, bottom first
+    fn show(calc: &Calculator) -> String {
+        let nums: Vec<String> = calc.stack().iter().map(|x| x.to_string()).collect();
+        nums.join(" ")
+    }
+
+    /// The stack after evaluating the input, which has to succeed
+    fn run(input: &str) -> String {
+        let mut calc = Calculator::new();
+        if let Err(err) = eval_lines(&mut calc, input) {
+            panic!("{:?} failed: {}", input, err);
+        }
+        show(&calc)
+    }
+
+    /// The error evaluating the input gives, along with the stack it leaves
+    fn fail(input: &str) -> (CalcError, String) {
+        let mut calc = Calculator::new();
+        let err = eval_lines(&mut calc, input).expect_err(input);
+        (err, show(&calc))
+    }
+
+    fn domain(op: &'static str, value: Value) -> CalcError {
+        CalcError::DomainError { op, value }
+    }
+
+    fn underflow(needed: usize, available: usize) -> CalcError {
+        CalcError::StackUnderflow { needed, available }
+    }
+
+    fn invalid_expression(reason: &str) -> CalcError {
+        CalcError::InvalidExpression(reason.to_string())
+    }
+
+    fn parse_error(token: &str) -> CalcError {
+        ParseError { token: token.to_string() }.into()
+    }
+
+    #[test]
+    fn evaluates_every_token_on_a_line() {
+        assert_eq!(run("2 3 + 4 *"), "20");
+        assert_eq!(run("  1   2\t3  "), "1 2 3");
+        assert_eq!(run("1 2 3 clear"), "");
+        assert_eq!(fail("1 2 bogus 3"), (parse_error("bogus"), "1 2".to_string()));
+    }
+
+    #[test]
+    fn can_be_driven_one_operation_at_a_time() {
+        let mut calc = Calculator::new();
+        calc.push(2);
+        calc.push(3);
+        calc.apply(StackOp::Pow).unwrap();
+        assert_eq!(show(&calc), "8");
+        assert_eq!(calc.apply(StackOp::Add), Err(underflow(2, 1)));
+        assert_eq!(show(&calc), "8");
+    }
+
+    #[test]
+    fn reports_errors_and_leaves_the_stack_alone() {
+        assert_eq!(fail("+"), (underflow(2, 0), String::new()));
+        assert_eq!(fail("1 +"), (underflow(2, 1), "1".to_string()));
+        assert_eq!(fail("1 0 /"), (CalcError::DivisionByZero, "1 0".to_string()));
+        assert_eq!(fail("-1 fact"), (domain("fact", Value::from(-1)), "-1".to_string()));
+        assert_eq!(fail("1 2.5 fact"), (domain("fact", Value::Float(2.5)), "1 2.5".to_string()));
+    }
+
+    #[test]
+    fn undoes_and_redoes() {
+        assert_eq!(run("1 2 + undo"), "1 2");
+        assert_eq!(run("1 2 + undo redo"), "3");
+        assert_eq!(run("1 2 3 clear u"), "1 2 3");
+        assert_eq!(fail("undo").0, CalcError::NothingToUndo);
+        assert_eq!(fail("1 redo").0, CalcError::NothingToRedo);
+        assert_eq!(fail("1 2 + undo 5 redo").0, CalcError::NothingToRedo);
+
+        let mut calc = Calculator::with_history_depth(2);
+        eval_lines(&mut calc, "1 2 3 undo undo").unwrap();
+        assert_eq!(calc.undo(), Err(CalcError::NothingToUndo));
+        assert_eq!(show(&calc), "1");
+    }
+
+    #[test]
+    fn undoes_changes_to_variables() {
+        assert_eq!(fail("5 sto x undo x"), (parse_error("x"), "5".to_string()));
+        assert_eq!(run("5 sto x undo redo x"), "5");
+        assert_eq!(run("5 sto x 6 sto x undo x"), "6 5");
+    }
+
+    #[test]
+    fn undoes_changes_to_settings() {
+        assert_eq!(run("deg undo 90 sin"), run("90 sin"));
+        assert_eq!(run("1 2 hex fix 4 undo undo undo"), "1");
+
+        let mut calc = Calculator::new();
+        eval_lines(&mut calc, "mode decimal precision 5 undo").unwrap();
+        assert_eq!(calc.mode(), NumMode::Decimal);
+        assert_eq!(calc.precision(), DEFAULT_PRECISION);
+    }
+
+    #[test]
+    fn defines_words() {
+        assert_eq!(run(": sq dup * ; 3 sq"), "9");
+        assert_eq!(run(": sq dup * ;\n: quad sq sq ;\n2 quad"), "16");
+        // words are expanded when they're defined
+        assert_eq!(run(": one 1 ; : two one one + ; : one 10 ; two"), "2");
+        assert_eq!(run(": sq dup * ; 1 2 + sq undo"), "3");
+        assert_eq!(fail(": bad 1 + ; bad"), (underflow(2, 1), String::new()));
+        assert!(matches!(fail(": 1 ;").0, CalcError::InvalidDefinition(_)));
+        assert!(matches!(fail(": 1/2 ;").0, CalcError::InvalidDefinition(_)));
+        assert!(matches!(fail(": sto 1 ;").0, CalcError::InvalidDefinition(_)));
+        assert!(matches!(fail(": word ;").0, CalcError::InvalidDefinition(_)));
+        assert_eq!(run(": dup 2 ; 1 dup"), "1 2");
+        assert!(matches!(fail(": sq bogus ;").0, CalcError::Parse(_)));
+
+        let mut calc = Calculator::new();
+        eval_lines(&mut calc, ": sq dup *").unwrap();
+        assert!(calc.is_defining());
+        eval_lines(&mut calc, "; 4 sq").unwrap();
+        assert_eq!(show(&calc), "16");
+    }
+
+    #[test]
+    fn stores_and_recalls_variables() {
+        assert_eq!(run("5 sto x x x *"), "25");
+        assert_eq!(run("5 sto x rcl x"), "5");
+        assert_eq!(fail("rcl y").0, CalcError::UndefinedVariable("y".to_string()));
+        assert_eq!(fail("y").0, parse_error("y"));
+        assert_eq!(fail("sto x").0, underflow(1, 0));
+    }
+
+    #[test]
+    fn only_stores_variables_that_can_be_recalled() {
+        assert_eq!(fail("5 sto max"), (parse_error("sto max"), "5".to_string()));
+        assert_eq!(fail("5 sto dup"), (parse_error("sto dup"), "5".to_string()));
+        assert_eq!(fail("5 sto i"), (parse_error("sto i"), "5".to_string()));
+        assert_eq!(fail("5 sto 1e3"), (parse_error("sto 1e3"), "5".to_string()));
+        // variables hide constants
+        assert_eq!(run("5 sto c c"), "5");
+        assert_eq!(run("5 sto pi pi 2 *"), "10");
+        assert_eq!(run("5 sto e\n= e + 1"), "6");
+    }
+
+    #[test]
+    fn computes_with_decimals() {
+        assert_eq!(run("mode decimal 0.1 0.2 +"), "0.3");
+        assert_eq!(run("mode decimal precision 5 2 3 /"), "0.66667");
+        assert_eq!(run("mode decimal 1.5 2 ^"), "2.25");
+        assert_eq!(run("mode decimal 2 -2 ^"), "0.25");
+        assert_eq!(run("0.1 0.2 +"), "0.30000000000000004");
+        assert_eq!(fail("mode decimal 1e999999999 1 +").0, parse_error("1e999999999"));
+        assert_eq!(fail("mode decimal 1e-999999999 1 +").0, parse_error("1e-999999999"));
+    }
+
+    #[test]
+    fn bounds_the_work_decimals_take() {
+        let mut calc = Calculator::new();
+        eval_lines(&mut calc, "precision 1000000000").unwrap();
+        assert_eq!(calc.precision(), MAX_PRECISION);
+        calc.set_precision(0);
+        assert_eq!(calc.precision(), 1);
+
+        assert_eq!(run("mode decimal precision 10 2.5 1000000000 ^"), "4699348028e+397939999");
+    }
+
+    #[test]
+    fn computes_with_fractions() {
+        assert_eq!(run("mode rational 1/3 1/6 +"), "1/2");
+        assert_eq!(run("mode rational 1 3 / 3 *"), "1");
+        assert_eq!(run("mode rational 0.75"), "3/4");
+        assert_eq!(run("0.5 to frac"), "1/2");
+        assert_eq!(run("mode exact 1/4 ->float"), "0.25");
+        assert_eq!(fail("mode rational 1e999999999").0, parse_error("1e999999999"));
+    }
+
+    #[test]
+    fn computes_with_complex_numbers() {
+        assert_eq!(run("-4 sqrt"), "2i");
+        assert_eq!(run("3+4i abs"), "5");
+        assert_eq!(run("2i 2i *"), "-4");
+        assert_eq!(run("1+i conj"), "1-1i");
+        assert_eq!(run("3+4i re 3+4i im"), "3 4");
+    }
+
+    #[test]
+    fn computes_with_big_integers() {
+        assert_eq!(run("2 100 ^"), "1267650600228229401496703205376");
+        assert_eq!(run("20 fact 10 fact div"), "670442572800");
+        assert_eq!(run("7 2 div 7 2 mod -7 2 mod"), "3 1 1");
+        assert_eq!(run("12 18 gcd 4 6 lcm"), "6 12");
+        assert_eq!(run("97 isprime 91 isprime"), "1 0");
+        assert_eq!(fail("7.5 2 mod").0, domain("mod", Value::Float(7.5)));
+    }
+
+    #[test]
+    fn bounds_the_size_of_big_integers() {
+        assert_eq!(run("10 1000000000 ^"), "inf");
+        assert_eq!(run("-1 1000000001 ^"), "-1");
+        assert_eq!(run("mode rational 3/2 1000000000 ^"), "inf");
+        assert_eq!(fail("100000000 fact").0, domain("fact", Value::from(100_000_000)));
+        assert_eq!(run("2 4096 ^ 1 - isprime"), "0");
+        assert_eq!(fail("2 4096 ^ isprime").0, domain("isprime", Value::Int(BigInt::from(1) << 4096)));
+    }
+
+    #[test]
+    fn works_on_machine_words() {
+        assert_eq!(run("0xff 0x0f and 0b1010 0b0101 or"), "15 15");
+        assert_eq!(run("1 4 shl 5 not"), "16 -6");
+        assert_eq!(run("word 8 unsigned 0x81 1 rol"), "3");
+        assert_eq!(run("word 8 unsigned 0x81 1 ror"), "192");
+        assert_eq!(run("word 8 unsigned -1 4 shr"), "15");
+        assert_eq!(run("word 8 -128 1 shr"), "-64");
+        assert_eq!(run("word 8 127 1 shl"), "-2");
+
+        let mut calc = Calculator::new();
+        eval_lines(&mut calc, "word 16 unsigned hex").unwrap();
+        assert_eq!(calc.word(), MachineWord::new(16, false).unwrap());
+        assert_eq!(calc.radix(), Radix::Hex);
+        let odd_size = CalcError::DomainError { op: "word", value: Value::from(12) };
+        assert_eq!(calc.apply(StackOp::SetWordSize(12)), Err(odd_size));
+        assert_eq!(calc.word(), MachineWord::new(16, false).unwrap());
+    }
+
+    #[test]
+    fn measures_angles_in_the_angle_mode() {
+        assert_eq!(run("deg 90 sin"), "1");
+        assert_eq!(run("grad 100 sin"), "1");
+        assert_eq!(run("deg 1 asin"), "90");
+        assert_eq!(run("180 to rad"), std::f64::consts::PI.to_string());
+        assert_eq!(run("rad 0 cos"), "1");
+    }
+
+    #[test]
+    fn changes_the_display_format() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.format(), DisplayFormat::Std);
+        eval_lines(&mut calc, "sci 3").unwrap();
+        assert_eq!(calc.format(), DisplayFormat::Sci(3));
+        eval_lines(&mut calc, "fix 1000000").unwrap();
+        assert_eq!(calc.format(), DisplayFormat::Fix(MAX_DIGITS));
+        calc.set_format(DisplayFormat::Eng(usize::MAX));
+        assert_eq!(calc.format(), DisplayFormat::Eng(MAX_DIGITS));
+    }
+
+    #[test]
+    fn shows_some_or_all_of_the_levels() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.levels(), None);
+        eval_lines(&mut calc, "show 4").unwrap();
+        assert_eq!(calc.levels(), Some(4));
+        eval_lines(&mut calc, "show all").unwrap();
+        assert_eq!(calc.levels(), None);
+    }
+
+    #[test]
+    fn manipulates_the_stack() {
+        assert_eq!(run("1 2 swap"), "2 1");
+        assert_eq!(run("1 2 over"), "1 2 1");
+        assert_eq!(run("1 2 3 rot"), "2 3 1");
+        assert_eq!(run("1 2 3 -rot"), "3 1 2");
+        assert_eq!(run("1 2 nip"), "2");
+        assert_eq!(run("1 2 tuck"), "2 1 2");
+        assert_eq!(run("1 2 2dup"), "1 2 1 2");
+        assert_eq!(run("1 2 3 2drop"), "1");
+        assert_eq!(run("1 2 3 4 2swap"), "3 4 1 2");
+        assert_eq!(run("1 2 3 2 pick"), "1 2 3 1");
+        assert_eq!(run("1 2 3 2 roll"), "2 3 1");
+        assert_eq!(run("1 2 3 depth"), "1 2 3 3");
+        assert_eq!(run("1 2 2 dupn"), "1 2 1 2");
+        assert_eq!(run("1 2 3 rotate"), "3 1 2");
+        assert_eq!(fail("1 2 5 pick").1, "1 2 5");
+    }
+
+    #[test]
+    fn evaluates_infix_expressions() {
+        assert_eq!(run("= (2 + 3) * 4"), "20");
+        assert_eq!(run("= 2 ^ 3 ^ 2"), "512");
+        assert_eq!(run("= -2 ^ 2"), "-4");
+        assert_eq!(run("= 3! + 7 % 4"), "9");
+        assert_eq!(run("= max(1, 5, 3)"), "5");
+        assert_eq!(run("1 2\n= 3 + 4\nundo"), "1 2");
+        assert_eq!(run("5 sto x\n= x * 2"), "10");
+        assert_eq!(run(": sq dup * ;\n= sq(3) + 1"), "10");
+        assert!(matches!(fail("= (1 + 2").0, CalcError::InvalidExpression(_)));
+        assert_eq!(fail("= 1 + bogus").0, parse_error("bogus"));
+    }
+
+    #[test]
+    fn gives_functions_only_their_own_arguments() {
+        assert_eq!(run("10 20\n= mean(1, 2)"), "10 20 1.5");
+        assert_eq!(run("10 20\n= sum(1, 2, 3)"), "10 20 6");
+        assert_eq!(run("10 20\n= depth()"), "10 20 0");
+        assert_eq!(fail("= sin(1, 2)"), (invalid_expression("sin can't take 2 argument(s)"), String::new()));
+        assert_eq!(fail("1\n= 1 + dup"), (invalid_expression("dup can't take 0 argument(s)"), "1".to_string()));
+        assert_eq!(fail("1\n= swap(2)"), (invalid_expression("swap can't take 1 argument(s)"), "1".to_string()));
+        let undo = invalid_expression("undo and redo can't be used in an expression");
+        assert_eq!(fail("1\n= 1 + undo"), (undo, "1".to_string()));
+    }
+
+    #[test]
+    fn summarises_the_stack() {
+        assert_eq!(run("1 2 3 4 mean"), "2.5");
+        assert_eq!(run("1 2 3 4 median"), "2.5");
+        assert_eq!(run("1 2 2 3 mode"), "2");
+        assert_eq!(run("1 2 3 4 5 2 nmean"), "1 2 3 4.5");
+        assert_eq!(run("2 4 4 4 5 5 7 9 pstdev"), "2");
+        assert_eq!(run("15 20 35 40 50 40 percentile"), "29");
+        assert_eq!(run("1 2 3 sum"), "6");
+        assert_eq!(run("1 2 3 prod"), "6");
+        assert_eq!(run("sum"), "0");
+        assert_eq!(run("prod"), "1");
+        assert_eq!(fail("5 nmean").0, underflow(6, 1));
+        assert_eq!(fail("1 stdev").0, underflow(2, 1));
+
+        assert_eq!(run("1_m 2_m 3_m mean"), "2_m");
+        assert_eq!(run("1_m 2_m 3_m stdev"), "1_m");
+        assert_eq!(run("1_km 500_m 2 nmean"), "0.75_km");
+        let sample = Value::parse("[1_m 1_s]", NumMode::Float).unwrap();
+        assert_eq!(fail("1_m 1_s mean"), (domain("mean", sample), "1_m 1_s".to_string()));
+    }
+
+    #[test]
+    fn fits_lines() {
+        assert_eq!(run("1 3 2 5 3 7 linreg"), "2 1 1");
+        assert_eq!(run("1 3 2 5 3 7 linreg 2drop drop 10 predict"), "21");
+        assert_eq!(fail("10 predict"), (CalcError::NoFit, "10".to_string()));
+        assert_eq!(fail("1 2 linreg").0, underflow(4, 2));
+        assert_eq!(fail("1 3 2 5 3 linreg").0, CalcError::UnpairedValue(Value::from(3)));
+        assert_eq!(fail("1 3 2 5 3 7 linreg undo 10 predict").0, CalcError::NoFit);
+        assert_eq!(run("1 3 2 5 3 7 linreg undo redo 2drop drop 10 predict"), "21");
+        assert_eq!(fail("1 2 1 3 linreg").0, domain("linreg", Value::from(1)));
+    }
+
+    #[test]
+    fn computes_with_lists() {
+        assert_eq!(run("[1 2 3] 2 *"), "[2 4 6]");
+        assert_eq!(run("[1 2] [3 4] +"), "[4 6]");
+        assert_eq!(run("[1 2 3] [4 5 6] dot"), "32");
+        assert_eq!(run("[1 0 0] [0 1 0] cross"), "[0 0 1]");
+        assert_eq!(run("[1 2 3] explode"), "1 2 3 3");
+        assert_eq!(run("1 2 3 3 collect"), "[1 2 3]");
+        assert_eq!(run("[1 2 3] 1 get [1 2] 3 concat len"), "2 3");
+        assert_eq!(run("[4 9] sqrt"), "[2 3]");
+        let mismatch = CalcError::DimensionMismatch { op: "+", left: vec![3], right: vec![2] };
+        assert_eq!(fail("[1 2 3] [1 2] +"), (mismatch, "[1 2 3] [1 2]".to_string()));
+        assert_eq!(fail("[1 0] 1 swap /").0, CalcError::DivisionByZero);
+    }
+
+    #[test]
+    fn computes_with_matrices() {
+        assert_eq!(run("[[1 2][3 4]] det"), "-2");
+        assert_eq!(run("[[1 2][3 4]] inv"), "[[-2 1][1.5 -0.5]]");
+        assert_eq!(run("mode rational [[1 2][3 4]] inv"), "[[-2 1][3/2 -1/2]]");
+        assert_eq!(run("[[1 2][3 4]] transpose trace"), "5");
+        assert_eq!(run("[[1 2][3 4]] [1 1] *"), "[3 7]");
+        assert_eq!(run("[[1 2][3 4]] identity 2 *"), "[[1 2][3 4]]");
+        assert_eq!(run("[[2 1][1 3]] [3 5] solve"), "[0.8 1.4]");
+        let mismatch = CalcError::DimensionMismatch { op: "*", left: vec![2, 2], right: vec![1, 3] };
+        assert_eq!(fail("[[1 2][3 4]] [[1 2 3]] *").0, mismatch);
+        assert_eq!(run("[[2 4][6 8]] 2 /"), "[[1 2][3 4]]");
+        let square = Value::parse("[[1 2][3 4]]", NumMode::Float).unwrap();
+        assert_eq!(fail("[[1 2][3 4]] [[1 2][3 4]] /").0, domain("/", square));
+        let singular = Value::parse("[[1 2][2 4]]", NumMode::Float).unwrap();
+        assert_eq!(fail("[[1 2][2 4]] inv").0, domain("inv", singular));
+    }
+
+    #[test]
+    fn computes_with_units() {
+        assert_eq!(run("1_km 500_m +"), "1.5_km");
+        assert_eq!(run("2_m 3_m *"), "6_m^2");
+        assert_eq!(run("10_m 2_s /"), "5_m/s");
+        assert_eq!(run("3_m 2 ^"), "9_m^2");
+        assert_eq!(run("1_km convert m"), "1000_m");
+        assert_eq!(run("mode decimal 90_km/h convert m/s"), "25_m/s");
+        assert_eq!(run("6_m 2_m /"), "3");
+
+        let units = |left: &str, right: &str| CalcError::IncompatibleUnits {
+            op: "+",
+            left: Unit::parse(left).unwrap(),
+            right: Unit::parse(right).unwrap(),
+        };
+        assert_eq!(fail("1_m 1_s +"), (units("m", "s"), "1_m 1_s".to_string()));
+        assert!(matches!(fail("1_m convert s").0, CalcError::IncompatibleUnits { op: "convert", .. }));
+
+        let huge = Value::parse("1_m^1000", NumMode::Float).unwrap();
+        assert_eq!(fail("1_m^1000 1_m *"), (domain("*", huge.clone()), "1_m^1000 1_m".to_string()));
+        assert_eq!(fail("1_m^1000 1_m^-1 /"), (domain("/", huge), "1_m^1000 1_m^-1".to_string()));
+        assert_eq!(fail("1_m^2147483647").0, parse_error("1_m^2147483647"));
+    }
+
+    #[test]
+    fn pushes_constants() {
+        assert_eq!(run("pi"), std::f64::consts::PI.to_string());
+        assert_eq!(run("c"), "299792458_m/s");
+        assert_eq!(run("mode decimal g 2_s *"), "19.6133_m/s");
+        assert_eq!(run("mode decimal pi"), std::f64::consts::PI.to_string());
+        assert_eq!(run("= 2 * tau / τ"), "2");
+    }
+}
@@ -0,0 +1,146 @@
+use value::{NumMode, Value};
+
+/// A number that can be pushed by name, such as pi or the speed of light
+#[derive(Debug)]
+pub struct Constant {
+    pub names: &'static [&'static str], // every name it goes by, starting with the one it's listed under
+    pub value: &'static str,            // written the way it would be typed in, with its unit if it has one
+    pub exact: bool,                    // whether the value is exact, rather than a rounded irrational number
+    pub description: &'static str,
+}
+
+/// Every constant, mathematical ones first
+pub const CONSTANTS: &[Constant] = &[
+    Constant {
+        names: &["pi", "π"],
+        value: "3.141592653589793",
+        exact: false,
+        description: "The ratio of a circle's circumference to its diameter",
+    },
+    Constant {
+        names: &["tau", "τ"],
+        value: "6.283185307179586",
+        exact: false,
+        description: "The ratio of a circle's circumference to its radius, 2π",
+    },
+    Constant {
+        names: &["e"],
+        value: "2.718281828459045",
+        exact: false,
+        description: "Euler's number, the base of the natural logarithm",
+    },
+    Constant {
+        names: &["phi", "φ", "ϕ"],
+        value: "1.618033988749895",
+        exact: false,
+        description: "The golden ratio, (1 + √5) / 2",
+    },
+    Constant {
+        names: &["sqrt2", "√2"],
+        value: "1.4142135623730951",
+        exact: false,
+        description: "The square root of 2",
+    },
+    Constant {
+        names: &["gamma", "γ"],
+        value: "0.5772156649015329",
+        exact: false,
+        description: "The Euler-Mascheroni constant",
+    },
+    Constant {
+        names: &["inf", "∞"],
+        value: "inf",
+        exact: false,
+        description: "Infinity",
+    },
+    Constant {
+        names: &["nan"],
+        value: "nan",
+        exact: false,
+        description: "Not a number, the result of undefined operations on floats",
+    },
+    Constant {
+        names: &["c"],
+        value: "299792458_m/s",
+        exact: true,
+        description: "The speed of light in vacuum",
+    },
+    Constant {
+        names: &["h", "planck"],
+        value: "6.62607015e-34_J*s",
+        exact: true,
+        description: "The Planck constant",
+    },
+    Constant {
+        names: &["na", "avogadro"],
+        value: "6.02214076e23_mol^-1",
+        exact: true,
+        description: "The Avogadro constant",
+    },
+    Constant {
+        names: &["k", "boltzmann"],
+        value: "1.380649e-23_J/K",
+        exact: true,
+        description: "The Boltzmann constant",
+    },
+    Constant {
+        names: &["g"],
+        value: "9.80665_m/s^2",
+        exact: true,
+        description: "Standard gravity, the acceleration of free fall on Earth",
+    },
+];
+
+impl Constant {
+    /// Looks a constant up by any of its names
+    pub fn find(name: &str) -> Option<&'static Constant> {
+        CONSTANTS.iter().find(|constant| constant.names.contains(&name))
+    }
+
+    /// The value of the constant. Exact constants are represented according to the mode,
+    /// while irrational ones are always floats, as they're only known to a float's precision.
+    pub fn to_value(&self, mode: NumMode) -> Value {
+        let mode = if self.exact { mode } else { NumMode::Float };
+        Value::parse(self.value, mode).expect("constants are numbers")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_constants_by_any_name() {
+        assert_eq!(Constant::find("pi").map(|c| c.names[0]), Some("pi"));
+        assert_eq!(Constant::find("π").map(|c| c.names[0]), Some("pi"));
+        assert_eq!(Constant::find("avogadro").map(|c| c.names[0]), Some("na"));
+        assert!(Constant::find("pie").is_none());
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let mut names: Vec<&str> = CONSTANTS.iter().flat_map(|c| c.names.iter().cloned()).collect();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn every_constant_is_a_number() {
+        for constant in CONSTANTS {
+            for &mode in &[NumMode::Float, NumMode::Decimal, NumMode::Rational] {
+                constant.to_value(mode);
+            }
+        }
+    }
+
+    #[test]
+    fn only_exact_constants_follow_the_mode() {
+        let pi = Constant::find("pi").unwrap().to_value(NumMode::Decimal);
+        assert_eq!(pi, Value::Float(std::f64::consts::PI));
+        let g = Constant::find("g").unwrap().to_value(NumMode::Decimal);
+        assert_eq!(g, Value::parse("9.80665_m/s^2", NumMode::Decimal).unwrap());
+        assert_eq!(g.to_string(), "9.80665_m/s^2");
+    }
+}
@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fmt;
+
+use parser::ParseError;
+use unit::Unit;
+use value::Value;
+
+/// Everything that can go wrong while evaluating an operation.
+/// Whenever one of these is returned, the stack is left as it was.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcError {
+    /// The operation needs more elements than the stack holds
+    StackUnderflow { needed: usize, available: usize },
+    /// The operation isn't defined for the given value, such as sqrt of -1
+    DomainError { op: &'static str, value: Value },
+    /// The operation combines lists element by element, but their lengths differ
+    DimensionMismatch { op: &'static str, left: Vec<usize>, right: Vec<usize> },
+    /// The operation needs numbers that measure the same kind of thing, such as m and ft
+    IncompatibleUnits { op: &'static str, left: Unit, right: Unit },
+    /// Attempted to divide by zero
+    DivisionByZero,
+    /// There are no more operations to undo
+    NothingToUndo,
+    /// There are no undone operations to redo
+    NothingToRedo,
+    /// Attempted to recall a variable that was never stored
+    UndefinedVariable(String),
+    /// Attempted to predict before fitting a line with linreg
+    NoFit,
+    /// linreg reads the stack as x y pairs, but the last x has no y
+    UnpairedValue(Value),
+    /// A word definition that can't be completed
+    InvalidDefinition(String),
+    /// An infix expression that can't be converted to RPN
+    InvalidExpression(String),
+    /// A token that isn't a known operation or number
+    Parse(ParseError),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::CalcError::*;
+
+        match *self {
+            StackUnderflow { needed, available } => write!(
+                f,
+                "Stack underflow: needed {} element(s), but only {} available",
+                needed, available
+            ),
+            DomainError { op, ref value } => write!(f, "{} is undefined for {}", op, value),
+            DimensionMismatch { op, ref left, ref right } => write!(
+                f,
+                "{} needs matching dimensions, but got {} and {}",
+                op,
+                format_shape(left),
+                format_shape(right)
+            ),
+            IncompatibleUnits { op, ref left, ref right } => write!(
+                f,
+                "{} needs compatible units, but got {} and {}",
+                op,
+                format_unit(left),
+                format_unit(right)
+            ),
+            DivisionByZero => write!(f, "Division by zero"),
+            NothingToUndo => write!(f, "Nothing to undo"),
+            NothingToRedo => write!(f, "Nothing to redo"),
+            UndefinedVariable(ref name) => write!(f, "The variable {} is undefined", name),
+            NoFit => write!(f, "No line has been fitted yet, try linreg first"),
+            UnpairedValue(ref x) => write!(f, "linreg needs x y pairs, but {} has no y", x),
+            InvalidDefinition(ref reason) => write!(f, "Invalid definition: {}", reason),
+            InvalidExpression(ref reason) => write!(f, "Invalid expression: {}", reason),
+            Parse(ref err) => err.fmt(f),
+        }
+    }
+}
+
+/// Writes the dimensions of a value, as in 2x3, or "a number" if it has none
+fn format_shape(shape: &[usize]) -> String {
+    if shape.is_empty() {
+        return "a number".to_string();
+    }
+    let dims: Vec<String> = shape.iter().map(|n| n.to_string()).collect();
+    dims.join("x")
+}
+
+/// Writes a unit, or "no unit" for plain numbers
+fn format_unit(unit: &Unit) -> String {
+    if unit.is_none() {
+        return "no unit".to_string();
+    }
+    unit.to_string()
+}
+
+impl Error for CalcError {}
+
+impl From<ParseError> for CalcError {
+    fn from(err: ParseError) -> Self {
+        CalcError::Parse(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_errors() {
+        let underflow = CalcError::StackUnderflow { needed: 2, available: 1 };
+        assert_eq!(underflow.to_string(), "Stack underflow: needed 2 element(s), but only 1 available");
+        let domain = CalcError::DomainError { op: "sqrt", value: Value::from(-1) };
+        assert_eq!(domain.to_string(), "sqrt is undefined for -1");
+        let parse = CalcError::from(ParseError { token: "foo".to_string() });
+        assert_eq!(parse.to_string(), "Couldn't parse foo");
+    }
+
+    #[test]
+    fn describes_shapes() {
+        let mismatch = CalcError::DimensionMismatch { op: "+", left: vec![2, 3], right: Vec::new() };
+        assert_eq!(mismatch.to_string(), "+ needs matching dimensions, but got 2x3 and a number");
+    }
+
+    #[test]
+    fn describes_units() {
+        let units = CalcError::IncompatibleUnits { op: "+", left: Unit::parse("m/s").unwrap(), right: Unit::none() };
+        assert_eq!(units.to_string(), "+ needs compatible units, but got m/s and no unit");
+    }
+}
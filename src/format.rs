@@ -0,0 +1,227 @@
+use std::iter;
+
+use bigdecimal::{BigDecimal, RoundingMode};
+use num_bigint::BigInt;
+use num_traits::{Signed, Zero};
+
+use integer::MachineWord;
+use value::{self, Value};
+
+/// The base that integers are displayed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Radix {
+    Bin,
+    Oct,
+    #[default]
+    Dec,
+    Hex,
+}
+
+impl Radix {
+    /// Formats an integer in this base, prefixed like the literals the parser accepts, such as 0x1F.
+    /// Integers that fit in the word are shown as its bit pattern, so -1 is 0xFF in an 8-bit word.
+    /// Bigger ones keep their sign, as in -0x1FF.
+    pub fn format(self, n: &BigInt, word: MachineWord) -> String {
+        let (prefix, radix) = match self {
+            Radix::Bin => ("0b", 2),
+            Radix::Oct => ("0o", 8),
+            Radix::Dec => return n.to_string(),
+            Radix::Hex => ("0x", 16),
+        };
+        if let Some(bits) = word.bit_pattern(n) {
+            return format!("{}{}", prefix, bits.to_str_radix(radix).to_uppercase());
+        }
+        let sign = if n.is_negative() { "-" } else { "" };
+
+        format!("{}{}{}", sign, prefix, n.abs().to_str_radix(radix).to_uppercase())
+    }
+}
+
+/// The most digits fix, sci and eng can show, which is already more than any number on the stack has
+pub const MAX_DIGITS: usize = 100;
+
+/// How numbers are displayed, after HP calculators.
+/// The format only changes what is shown; the numbers on the stack keep their precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplayFormat {
+    #[default]
+    Std,        // as many digits as the number needs, as in 0.004, with fractions shown as such
+    Fix(usize), // a fixed number of decimals, as in 0.00 for fix 2
+    Sci(usize), // scientific notation with that many decimals, as in 4.00e-3 for sci 2
+    Eng(usize), // like sci, but with the exponent a multiple of three, as in 40.0e-3 for eng 2
+}
+
+impl DisplayFormat {
+    /// The same format with at most MAX_DIGITS digits
+    pub fn clamped(self) -> DisplayFormat {
+        match self {
+            DisplayFormat::Std => DisplayFormat::Std,
+            DisplayFormat::Fix(digits) => DisplayFormat::Fix(digits.min(MAX_DIGITS)),
+            DisplayFormat::Sci(digits) => DisplayFormat::Sci(digits.min(MAX_DIGITS)),
+            DisplayFormat::Eng(digits) => DisplayFormat::Eng(digits.min(MAX_DIGITS)),
+        }
+    }
+
+    /// Formats a number in this format. Integers and fractions are exact,
+    /// so std shows them as they are, while fix, sci and eng round them like any other number.
+    pub fn format(self, num: &Value) -> String {
+        match *num {
+            Value::Int(_) | Value::Rational(_) if self == DisplayFormat::Std => num.to_string(),
+            Value::Int(ref n) => self.format_decimal(&BigDecimal::from(n.clone())),
+            Value::Rational(ref x) => {
+                // a numerator of b bits has at most b / 3 + 1 digits, so this keeps every digit shown
+                let precision = x.numer().bits() / 3 + 1 + MAX_DIGITS as u64 + 1;
+                self.format_decimal(&value::rational_to_decimal(x, precision))
+            }
+            Value::Float(x) => self.format_float(x),
+            Value::Decimal(ref x) => self.format_decimal(x),
+            Value::Complex(z) => value::format_complex(z, |x| self.format_float(x)),
+            Value::List(ref xs) => {
+                let xs: Vec<String> = xs.iter().map(|x| self.format(x)).collect();
+                format!("[{}]", xs.join(" "))
+            }
+            Value::Matrix(ref m) => {
+                let rows: Vec<String> = m.iter().map(|row| self.format(&Value::List(row.clone()))).collect();
+                format!("[{}]", rows.concat())
+            }
+            Value::Quantity(ref x, ref unit) => format!("{}_{}", self.format(x), unit),
+        }
+    }
+
+    /// Formats a float; infinities and NaN are always shown as they are
+    fn format_float(self, x: f64) -> String {
+        if !x.is_finite() {
+            return x.to_string();
+        }
+
+        match self {
+            DisplayFormat::Std => x.to_string(),
+            DisplayFormat::Fix(decimals) => format!("{:.*}", decimals, x),
+            DisplayFormat::Sci(decimals) | DisplayFormat::Eng(decimals) => {
+                // rust rounds the digits for us, as in 4.00e-3
+                let formatted = format!("{:.*e}", decimals, x.abs());
+                let (mantissa, exponent) = formatted.split_once('e').expect("formatted with {:e}");
+                let exponent = exponent.parse().expect("formatted with {:e}");
+                self.scientific(x < 0.0, &mantissa.replace('.', ""), exponent)
+            }
+        }
+    }
+
+    /// Formats a decimal without going through a float, so no digits are lost
+    fn format_decimal(self, x: &BigDecimal) -> String {
+        match self {
+            DisplayFormat::Std => x.normalized().to_string(),
+            DisplayFormat::Fix(decimals) => x.with_scale_round(decimals as i64, RoundingMode::HalfEven).to_plain_string(),
+            DisplayFormat::Sci(decimals) | DisplayFormat::Eng(decimals) => {
+                if x.is_zero() {
+                    return self.scientific(false, "0", 0);
+                }
+                let (digits, scale) = x.abs().with_prec(decimals as u64 + 1).as_bigint_and_exponent();
+                let digits = digits.to_string();
+                let exponent = digits.len() as i64 - 1 - scale;
+                self.scientific(x.is_negative(), &digits, exponent)
+            }
+        }
+    }
+
+    /// Lays out the significant digits of a number, whose first digit is
+    /// at 10^exponent, in scientific or engineering notation.
+    /// Missing digits are filled in with zeros, and superfluous ones are dropped.
+    fn scientific(self, negative: bool, digits: &str, exponent: i64) -> String {
+        let (decimals, shift) = match self {
+            DisplayFormat::Sci(decimals) => (decimals, 0),
+            DisplayFormat::Eng(decimals) => (decimals, exponent.rem_euclid(3) as usize),
+            _ => unreachable!("only sci and eng use scientific notation"),
+        };
+        let whole = shift + 1; // the number of digits before the point
+        let digits: String = digits.chars().chain(iter::repeat('0')).take(whole.max(decimals + 1)).collect();
+        let (whole_digits, fraction) = digits.split_at(whole);
+
+        let sign = if negative { "-" } else { "" };
+        let point = if fraction.is_empty() { "" } else { "." };
+        format!("{}{}{}{}e{}", sign, whole_digits, point, fraction, exponent - shift as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_rational::BigRational;
+    use std::str::FromStr;
+
+    fn word(bits: u32, signed: bool) -> MachineWord {
+        MachineWord::new(bits, signed).unwrap()
+    }
+
+    fn decimal(input: &str) -> Value {
+        Value::Decimal(BigDecimal::from_str(input).unwrap())
+    }
+
+    fn fraction(numer: i64, denom: i64) -> Value {
+        Value::Rational(BigRational::new(BigInt::from(numer), BigInt::from(denom)))
+    }
+
+    #[test]
+    fn formats_integers_in_other_bases() {
+        let n = BigInt::from(31);
+        assert_eq!(Radix::Hex.format(&n, MachineWord::default()), "0x1F");
+        assert_eq!(Radix::Oct.format(&n, MachineWord::default()), "0o37");
+        assert_eq!(Radix::Bin.format(&n, MachineWord::default()), "0b11111");
+        assert_eq!(Radix::Dec.format(&-n, MachineWord::default()), "-31");
+    }
+
+    #[test]
+    fn formats_negative_integers_as_bit_patterns_of_the_word() {
+        let minus_one = BigInt::from(-1);
+        assert_eq!(Radix::Hex.format(&minus_one, word(8, false)), "0xFF");
+        assert_eq!(Radix::Hex.format(&minus_one, word(8, true)), "0xFF");
+        assert_eq!(Radix::Hex.format(&minus_one, word(16, true)), "0xFFFF");
+        assert_eq!(Radix::Bin.format(&BigInt::from(-128), word(8, true)), "0b10000000");
+        // numbers that don't fit in the word keep their sign
+        assert_eq!(Radix::Hex.format(&BigInt::from(-300), word(8, true)), "-0x12C");
+        assert_eq!(Radix::Hex.format(&BigInt::from(256), word(8, false)), "0x100");
+    }
+
+    #[test]
+    fn formats_floats() {
+        assert_eq!(DisplayFormat::Std.format(&Value::Float(0.004)), "0.004");
+        assert_eq!(DisplayFormat::Fix(2).format(&Value::Float(0.004)), "0.00");
+        assert_eq!(DisplayFormat::Fix(2).format(&Value::Float(2.0 / 3.0)), "0.67");
+        assert_eq!(DisplayFormat::Sci(2).format(&Value::Float(0.004)), "4.00e-3");
+        assert_eq!(DisplayFormat::Sci(1).format(&Value::Float(-12345.0)), "-1.2e4");
+        assert_eq!(DisplayFormat::Eng(2).format(&Value::Float(0.04)), "40.0e-3");
+        assert_eq!(DisplayFormat::Eng(3).format(&Value::Float(123456.0)), "123.5e3");
+        assert_eq!(DisplayFormat::Fix(2).format(&Value::Float(f64::INFINITY)), "inf");
+    }
+
+    #[test]
+    fn formats_decimals_without_losing_digits() {
+        assert_eq!(DisplayFormat::Std.format(&decimal("1.500")), "1.5");
+        assert_eq!(DisplayFormat::Fix(3).format(&decimal("2.0005")), "2.000");
+        assert_eq!(DisplayFormat::Fix(25).format(&decimal("0.1")), "0.1000000000000000000000000");
+        assert_eq!(DisplayFormat::Sci(2).format(&decimal("-0.0012345")), "-1.23e-3");
+        assert_eq!(DisplayFormat::Sci(2).format(&decimal("0")), "0.00e0");
+        assert_eq!(DisplayFormat::Eng(2).format(&decimal("12345")), "12.3e3");
+    }
+
+    #[test]
+    fn only_leaves_exact_numbers_alone_in_std() {
+        assert_eq!(DisplayFormat::Std.format(&Value::from(1234567)), "1234567");
+        assert_eq!(DisplayFormat::Std.format(&fraction(-1, 3)), "-1/3");
+        assert_eq!(DisplayFormat::Fix(2).format(&Value::from(5)), "5.00");
+        assert_eq!(DisplayFormat::Sci(3).format(&Value::from(1234567)), "1.235e6");
+        assert_eq!(DisplayFormat::Eng(2).format(&Value::from(-12345)), "-12.3e3");
+        assert_eq!(DisplayFormat::Fix(3).format(&fraction(2, 3)), "0.667");
+        assert_eq!(DisplayFormat::Sci(2).format(&fraction(-1, 8)), "-1.25e-1");
+        assert_eq!(DisplayFormat::Fix(40).format(&fraction(1, 3)), format!("0.{}", "3".repeat(40)));
+        let xs = Value::List(vec![Value::from(1), Value::Float(2.5)]);
+        assert_eq!(DisplayFormat::Sci(2).format(&xs), "[1.00e0 2.50e0]");
+    }
+
+    #[test]
+    fn caps_the_number_of_digits() {
+        assert_eq!(DisplayFormat::Fix(1_000_000).clamped(), DisplayFormat::Fix(MAX_DIGITS));
+        assert_eq!(DisplayFormat::Sci(3).clamped(), DisplayFormat::Sci(3));
+        assert_eq!(DisplayFormat::Std.clamped(), DisplayFormat::Std);
+    }
+}
@@ -0,0 +1,316 @@
+use std::iter::Peekable;
+use std::mem;
+use std::str::Chars;
+
+use error::CalcError;
+
+/// A single piece of an infix expression
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Operand(String),  // a number, constant or variable
+    Function(String), // a name directly followed by "(", as in "sin(x)"
+    Operator(char),   // + - * / % ^, where + and - may also be unary
+    Factorial,        // the postfix !
+    Open,
+    Close,
+    Comma,
+}
+
+/// An operator that is waiting for its right operand to be complete
+#[derive(Debug, Clone, PartialEq)]
+enum Pending {
+    Operator(char),
+    Negate,
+    Paren(Option<String>, usize), // the function being called, if any, and its arguments so far
+}
+
+/// A single RPN token, along with how many numbers it takes off the stack.
+/// Every step gives back exactly one number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub token: String,
+    pub arity: usize,
+}
+
+impl Pending {
+    /// How tightly the operator binds; parentheses stop other operators from being popped
+    fn precedence(&self) -> u8 {
+        match *self {
+            Pending::Operator('+') | Pending::Operator('-') => 1,
+            Pending::Operator('^') => 4,
+            Pending::Operator(_) => 2,
+            Pending::Negate => 3, // so -2^2 is -(2^2)
+            Pending::Paren(..) => 0,
+        }
+    }
+
+    /// The RPN step the operator becomes
+    fn to_step(&self) -> Step {
+        let (token, arity) = match *self {
+            Pending::Operator('%') => ("mod".to_string(), 2),
+            Pending::Operator(op) => (op.to_string(), 2),
+            Pending::Negate => ("neg".to_string(), 1),
+            Pending::Paren(ref function, args) => (function.clone().unwrap_or_default(), args),
+        };
+        Step { token, arity }
+    }
+}
+
+/// Converts an infix expression, such as "(2 + 3) * sin(pi / 4)", into the tokens
+/// it stands for in RPN, such as ["2", "3", "+", "pi", "4", "/", "sin", "*"],
+/// using the shunting-yard algorithm.
+/// The usual precedence applies: ^ binds tighter than unary minus,
+/// which binds tighter than * / %, which bind tighter than + -. Only ^ is right-associative.
+/// Names directly followed by parentheses are functions, whose arguments are separated by commas.
+/// Any other name is passed on as it is, so it can be a constant, a variable or a user-defined word.
+pub fn to_rpn(expr: &str) -> Result<Vec<String>, CalcError> {
+    Ok(translate(expr)?.into_iter().map(|step| step.token).collect())
+}
+
+/// Converts an infix expression into RPN like `to_rpn`,
+/// keeping track of how many numbers each token takes, so they can be checked
+pub fn translate(expr: &str) -> Result<Vec<Step>, CalcError> {
+    let mut output = Vec::new();
+    let mut pending: Vec<Pending> = Vec::new();
+    let mut expect_operand = true;
+    let mut just_called = false; // whether the last token opened a function call
+
+    for token in lex(expr)? {
+        let after_call = mem::replace(&mut just_called, false);
+        match token {
+            Token::Operand(operand) if expect_operand => {
+                output.push(Step { token: operand, arity: 0 });
+                expect_operand = false;
+            }
+            Token::Function(name) if expect_operand => {
+                pending.push(Pending::Paren(Some(name), 0));
+                just_called = true;
+            }
+            Token::Open if expect_operand => pending.push(Pending::Paren(None, 0)),
+            Token::Operator('-') if expect_operand => pending.push(Pending::Negate),
+            Token::Operator('+') if expect_operand => (), // unary plus changes nothing
+            Token::Operator(op) if !expect_operand => {
+                let incoming = Pending::Operator(op);
+                let right_assoc = op == '^';
+                while let Some(top) = pending.last() {
+                    let higher = top.precedence() > incoming.precedence();
+                    let equal = top.precedence() == incoming.precedence();
+                    if !(higher || equal && !right_assoc) {
+                        break;
+                    }
+                    output.push(pending.pop().unwrap().to_step());
+                }
+                pending.push(incoming);
+                expect_operand = true;
+            }
+            Token::Factorial if !expect_operand => output.push(Step { token: "!".to_string(), arity: 1 }),
+            Token::Close | Token::Comma => {
+                // a function may be called without arguments, as in "depth()"
+                let no_arguments = after_call && token == Token::Close;
+                if expect_operand && !no_arguments {
+                    return Err(invalid_expression("missing an operand"));
+                }
+                let paren = pop_until_paren(&mut pending, &mut output);
+                if token == Token::Comma {
+                    match paren {
+                        Ok((Some(function), args)) => pending.push(Pending::Paren(Some(function), args + 1)),
+                        _ => return Err(invalid_expression("commas can only separate the arguments of a function")),
+                    }
+                    expect_operand = true;
+                } else {
+                    let (function, args) = paren?;
+                    if let Some(token) = function {
+                        let arity = if no_arguments { 0 } else { args + 1 };
+                        output.push(Step { token, arity });
+                    }
+                    expect_operand = false;
+                }
+            }
+            Token::Operand(operand) | Token::Function(operand) => {
+                return Err(invalid_expression(&format!("missing an operator before {}", operand)));
+            }
+            Token::Open => return Err(invalid_expression("missing an operator before (")),
+            Token::Operator(_) | Token::Factorial => return Err(invalid_expression("missing an operand")),
+        }
+    }
+
+    if output.is_empty() && pending.is_empty() {
+        return Err(invalid_expression("the expression is empty"));
+    }
+    if expect_operand {
+        return Err(invalid_expression("missing an operand"));
+    }
+    while let Some(top) = pending.pop() {
+        if let Pending::Paren(..) = top {
+            return Err(invalid_expression("missing a )"));
+        }
+        output.push(top.to_step());
+    }
+
+    Ok(output)
+}
+
+/// Moves the pending operators to the output up to the innermost open parenthesis,
+/// which is removed as well. Returns the function the parenthesis belongs to, if any,
+/// along with the arguments it had before the current one.
+fn pop_until_paren(pending: &mut Vec<Pending>, output: &mut Vec<Step>) -> Result<(Option<String>, usize), CalcError> {
+    while let Some(top) = pending.pop() {
+        match top {
+            Pending::Paren(function, args) => return Ok((function, args)),
+            op => output.push(op.to_step()),
+        }
+    }
+    Err(invalid_expression("missing a ("))
+}
+
+/// Splits an infix expression into tokens
+fn lex(expr: &str) -> Result<Vec<Token>, CalcError> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            c if is_word_char(c) => {
+                let word = lex_word(&mut chars);
+                if chars.peek() == Some(&'(') {
+                    chars.next();
+                    Token::Function(word)
+                } else {
+                    Token::Operand(word)
+                }
+            }
+            '+' | '-' | '*' | '/' | '%' | '^' => Token::Operator(c),
+            '!' => Token::Factorial,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            c => return Err(invalid_expression(&format!("unexpected {}", c))),
+        };
+        if !matches!(token, Token::Operand(_) | Token::Function(_)) {
+            chars.next();
+        }
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Reads a name or a number, including the sign of an exponent, as in 1e-3
+fn lex_word(chars: &mut Peekable<Chars>) -> String {
+    let mut word = String::new();
+
+    while let Some(&c) = chars.peek() {
+        let exponent_sign = (c == '+' || c == '-') && is_exponent(&word);
+        if !is_word_char(c) && !exponent_sign {
+            break;
+        }
+        word.push(c);
+        chars.next();
+    }
+
+    word
+}
+
+/// Whether a character can be part of a name or a number
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '.' || c == '_'
+}
+
+/// Whether a word is a number waiting for the sign of its exponent, as in the 1e of 1e-3
+fn is_exponent(word: &str) -> bool {
+    match word.strip_suffix(['e', 'E']) {
+        Some(mantissa) => !mantissa.starts_with("0x") && mantissa.parse::<f64>().is_ok(),
+        None => false,
+    }
+}
+
+/// Shorthand for rejecting an infix expression
+fn invalid_expression(reason: &str) -> CalcError {
+    CalcError::InvalidExpression(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpn(expr: &str) -> String {
+        to_rpn(expr).unwrap().join(" ")
+    }
+
+    fn arities(expr: &str) -> Vec<(String, usize)> {
+        translate(expr).unwrap().into_iter().map(|step| (step.token, step.arity)).collect()
+    }
+
+    fn is_invalid(expr: &str) -> bool {
+        matches!(to_rpn(expr), Err(CalcError::InvalidExpression(_)))
+    }
+
+    #[test]
+    fn follows_the_usual_precedence() {
+        assert_eq!(rpn("1 + 2 * 3"), "1 2 3 * +");
+        assert_eq!(rpn("(1 + 2) * 3"), "1 2 + 3 *");
+        assert_eq!(rpn("1 - 2 - 3"), "1 2 - 3 -");
+        assert_eq!(rpn("8 / 4 / 2"), "8 4 / 2 /");
+        assert_eq!(rpn("7 % 4 * 2"), "7 4 mod 2 *");
+        assert_eq!(rpn("(2 + 3) * sin(pi / 4)"), "2 3 + pi 4 / sin *");
+    }
+
+    #[test]
+    fn raises_powers_from_the_right() {
+        assert_eq!(rpn("2 ^ 3 ^ 2"), "2 3 2 ^ ^");
+        assert_eq!(rpn("2 * 3 ^ 2"), "2 3 2 ^ *");
+    }
+
+    #[test]
+    fn negates_after_powers() {
+        assert_eq!(rpn("-2 ^ 2"), "2 2 ^ neg");
+        assert_eq!(rpn("-2 * 3"), "2 neg 3 *");
+        assert_eq!(rpn("2 * -3"), "2 3 neg *");
+        assert_eq!(rpn("2 ^ -1"), "2 1 neg ^");
+        assert_eq!(rpn("--2"), "2 neg neg");
+    }
+
+    #[test]
+    fn reads_numbers_with_exponents_and_factorials() {
+        assert_eq!(rpn("1e-3 + 2E+2"), "1e-3 2E+2 +");
+        assert_eq!(rpn("0x1e-1"), "0x1e 1 -");
+        assert_eq!(rpn("3! + 1"), "3 ! 1 +");
+    }
+
+    #[test]
+    fn passes_function_arguments_in_order() {
+        assert_eq!(rpn("max(1, 2 + 3)"), "1 2 3 + max");
+        assert_eq!(rpn("atan(sin(x), cos(y))"), "x sin y cos atan");
+        assert_eq!(rpn("depth()"), "depth");
+    }
+
+    #[test]
+    fn counts_the_arguments_of_every_step() {
+        let steps = |pairs: &[(&str, usize)]| -> Vec<(String, usize)> {
+            pairs.iter().map(|&(token, arity)| (token.to_string(), arity)).collect()
+        };
+        assert_eq!(arities("-x + 3!"), steps(&[("x", 0), ("neg", 1), ("3", 0), ("!", 1), ("+", 2)]));
+        assert_eq!(arities("mean(1, 2, 3)"), steps(&[("1", 0), ("2", 0), ("3", 0), ("mean", 3)]));
+        assert_eq!(arities("sin((1))"), steps(&[("1", 0), ("sin", 1)]));
+        assert_eq!(arities("depth()"), steps(&[("depth", 0)]));
+        assert_eq!(arities("f(g(), 1)"), steps(&[("g", 0), ("1", 0), ("f", 2)]));
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert!(is_invalid(""));
+        assert!(is_invalid("1 +"));
+        assert!(is_invalid("1 2"));
+        assert!(is_invalid("(1 + 2"));
+        assert!(is_invalid("1 + 2)"));
+        assert!(is_invalid("()"));
+        assert!(is_invalid("1, 2"));
+        assert!(is_invalid("max(1,)"));
+        assert!(is_invalid("2 $ 3"));
+        assert!(is_invalid("!3"));
+    }
+}
@@ -0,0 +1,297 @@
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+
+/// The bases for the Miller-Rabin test, which together make it exact
+/// for every number below 3.3 * 10^24, and all but certain above that
+const WITNESSES: &[u32] = &[2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41];
+
+/// The most bits an exact power or factorial may have, around 300,000 digits.
+/// Anything bigger would take too long to work out.
+pub const MAX_BITS: u64 = 1_000_000;
+
+/// The most bits a number tested for being a prime may have, around 1,200 digits.
+/// The test takes time cubic in the bits, so anything bigger would take too long.
+pub const MAX_PRIME_BITS: u64 = 4096;
+
+/// Multiplies every number from 1 to n together
+pub fn factorial(n: u32) -> BigInt {
+    (2..=n).fold(BigInt::one(), |acc, k| acc * k)
+}
+
+/// Whether n! has at most MAX_BITS bits, estimated with Stirling's approximation
+pub fn factorial_fits(n: u32) -> bool {
+    if n < 2 {
+        return true;
+    }
+    let n = f64::from(n);
+    let ln = n * n.ln() - n + 0.5 * (2.0 * std::f64::consts::PI * n).ln();
+    ln / std::f64::consts::LN_2 <= MAX_BITS as f64
+}
+
+/// Whether a power of a number with the given bits, raised to exp, has at most MAX_BITS bits
+pub fn power_fits(bits: u64, exp: u64) -> bool {
+    bits.saturating_mul(exp) <= MAX_BITS
+}
+
+/// Checks whether n is a prime with the Miller-Rabin test
+pub fn is_prime(n: &BigInt) -> bool {
+    if n < &BigInt::from(2) {
+        return false;
+    }
+
+    for &p in WITNESSES {
+        let p = BigInt::from(p);
+        if *n == p {
+            return true;
+        }
+        if n.is_multiple_of(&p) {
+            return false;
+        }
+    }
+
+    // write n - 1 as d * 2^s with d odd
+    let n_minus_one: BigInt = n - 1;
+    let s = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> s;
+
+    WITNESSES.iter().all(|&a| {
+        let mut x = BigInt::from(a).modpow(&d, n);
+        if x.is_one() || x == n_minus_one {
+            return true;
+        }
+        for _ in 1..s {
+            x = x.modpow(&BigInt::from(2), n);
+            if x == n_minus_one {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// The greatest common divisor, which is never negative
+pub fn gcd(a: &BigInt, b: &BigInt) -> BigInt {
+    a.gcd(b).abs()
+}
+
+/// The least common multiple, which is never negative
+pub fn lcm(a: &BigInt, b: &BigInt) -> BigInt {
+    if a.is_zero() || b.is_zero() {
+        BigInt::zero()
+    } else {
+        a.lcm(b).abs()
+    }
+}
+
+/// The size and interpretation of the words that bitwise operations work on.
+/// Operands are wrapped to the word size in two's complement,
+/// and results are read back as signed or unsigned numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineWord {
+    bits: u32, // one of 8, 16, 32 or 64
+    signed: bool,
+}
+
+impl Default for MachineWord {
+    fn default() -> Self {
+        MachineWord { bits: 64, signed: true }
+    }
+}
+
+impl MachineWord {
+    /// A word of 8, 16, 32 or 64 bits, or None for any other size
+    pub fn new(bits: u32, signed: bool) -> Option<MachineWord> {
+        match bits {
+            8 | 16 | 32 | 64 => Some(MachineWord { bits, signed }),
+            _ => None,
+        }
+    }
+
+    /// The number of bits in the word
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Whether results are read back as signed numbers
+    pub fn signed(&self) -> bool {
+        self.signed
+    }
+
+    /// The same word, read back as signed or unsigned numbers
+    pub fn with_signed(self, signed: bool) -> MachineWord {
+        MachineWord { signed, ..self }
+    }
+
+    /// Every bit of the word set
+    fn mask(&self) -> BigInt {
+        (BigInt::one() << self.bits) - 1
+    }
+
+    /// The bit pattern of n in this word, as an unsigned number
+    fn wrap(&self, n: &BigInt) -> BigInt {
+        n & self.mask()
+    }
+
+    /// The bit pattern of n, as an unsigned number, if n fits in the word either signed or unsigned
+    pub fn bit_pattern(&self, n: &BigInt) -> Option<BigInt> {
+        let min = -(BigInt::one() << (self.bits - 1));
+        if *n >= min && *n <= self.mask() {
+            Some(self.wrap(n))
+        } else {
+            None
+        }
+    }
+
+    /// Reads a bit pattern back as a number, according to the signedness
+    fn interpret(&self, bits: BigInt) -> BigInt {
+        let bits = self.wrap(&bits);
+        if self.signed && bits.bit(u64::from(self.bits - 1)) {
+            bits - (BigInt::one() << self.bits)
+        } else {
+            bits
+        }
+    }
+
+    pub fn and(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        self.interpret(self.wrap(a) & self.wrap(b))
+    }
+
+    pub fn or(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        self.interpret(self.wrap(a) | self.wrap(b))
+    }
+
+    pub fn xor(&self, a: &BigInt, b: &BigInt) -> BigInt {
+        self.interpret(self.wrap(a) ^ self.wrap(b))
+    }
+
+    pub fn not(&self, a: &BigInt) -> BigInt {
+        self.interpret(self.wrap(a) ^ self.mask())
+    }
+
+    /// Shifts left, letting the bits fall off the top of the word
+    pub fn shl(&self, a: &BigInt, count: u32) -> BigInt {
+        let count = count.min(self.bits);
+        self.interpret(self.wrap(a) << count)
+    }
+
+    /// Shifts right, copying the sign bit in from the top for signed words
+    pub fn shr(&self, a: &BigInt, count: u32) -> BigInt {
+        let count = count.min(self.bits);
+        if self.signed {
+            self.interpret(self.interpret(a.clone()) >> count)
+        } else {
+            self.wrap(a) >> count
+        }
+    }
+
+    /// Rotates left, moving the bits that fall off the top back in at the bottom
+    pub fn rol(&self, a: &BigInt, count: u32) -> BigInt {
+        let count = count % self.bits;
+        let bits = self.wrap(a);
+        self.interpret((&bits << count) | (bits >> (self.bits - count)))
+    }
+
+    /// Rotates right, moving the bits that fall off the bottom back in at the top
+    pub fn ror(&self, a: &BigInt, count: u32) -> BigInt {
+        self.rol(a, self.bits - count % self.bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> BigInt {
+        BigInt::from(n)
+    }
+
+    const BYTE: MachineWord = MachineWord { bits: 8, signed: false };
+    const SIGNED_BYTE: MachineWord = MachineWord { bits: 8, signed: true };
+
+    #[test]
+    fn only_makes_words_of_the_usual_sizes() {
+        assert_eq!(MachineWord::new(8, false), Some(BYTE));
+        assert_eq!(MachineWord::new(64, true), Some(MachineWord::default()));
+        assert_eq!(MachineWord::new(0, true), None);
+        assert_eq!(MachineWord::new(12, false), None);
+        assert_eq!(BYTE.with_signed(true), SIGNED_BYTE);
+    }
+
+    #[test]
+    fn multiplies_factorials() {
+        assert_eq!(factorial(0), int(1));
+        assert_eq!(factorial(1), int(1));
+        assert_eq!(factorial(20), int(2_432_902_008_176_640_000));
+    }
+
+    #[test]
+    fn bounds_the_size_of_results() {
+        assert!(factorial_fits(0));
+        assert!(factorial_fits(10_000));
+        assert!(!factorial_fits(100_000));
+        assert!(!factorial_fits(u32::MAX));
+        assert!(power_fits(0, u64::MAX));
+        assert!(power_fits(4, MAX_BITS / 4));
+        assert!(!power_fits(4, MAX_BITS / 4 + 1));
+        assert!(!power_fits(u64::MAX, 2));
+    }
+
+    #[test]
+    fn tells_primes_apart() {
+        let primes: Vec<i64> = (0..50).filter(|&n| is_prime(&int(n))).collect();
+        assert_eq!(primes, vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47]);
+        assert!(is_prime(&int(2_147_483_647)));
+        assert!(!is_prime(&int(3_215_031_751))); // a strong pseudoprime to the bases 2, 3, 5 and 7
+        assert!(!is_prime(&int(-7)));
+    }
+
+    #[test]
+    fn finds_divisors_and_multiples() {
+        assert_eq!(gcd(&int(12), &int(-18)), int(6));
+        assert_eq!(gcd(&int(0), &int(5)), int(5));
+        assert_eq!(lcm(&int(4), &int(-6)), int(12));
+        assert_eq!(lcm(&int(0), &int(6)), int(0));
+    }
+
+    #[test]
+    fn wraps_to_the_word() {
+        assert_eq!(BYTE.not(&int(0)), int(255));
+        assert_eq!(SIGNED_BYTE.not(&int(0)), int(-1));
+        assert_eq!(BYTE.and(&int(-1), &int(0x0F)), int(0x0F));
+        assert_eq!(BYTE.or(&int(0x100), &int(1)), int(1));
+        assert_eq!(SIGNED_BYTE.xor(&int(0x7F), &int(0xFF)), int(-128));
+    }
+
+    #[test]
+    fn shifts_bits_off_the_ends() {
+        assert_eq!(BYTE.shl(&int(0x81), 1), int(0x02));
+        assert_eq!(SIGNED_BYTE.shl(&int(0x40), 1), int(-128));
+        assert_eq!(BYTE.shl(&int(1), 100), int(0));
+        assert_eq!(BYTE.shr(&int(0x80), 7), int(1));
+        assert_eq!(BYTE.shr(&int(-1), 4), int(0x0F));
+        // signed words copy the sign bit in
+        assert_eq!(SIGNED_BYTE.shr(&int(-128), 7), int(-1));
+        assert_eq!(SIGNED_BYTE.shr(&int(-128), 100), int(-1));
+        assert_eq!(SIGNED_BYTE.shr(&int(64), 6), int(1));
+    }
+
+    #[test]
+    fn rotates_bits_around() {
+        assert_eq!(BYTE.rol(&int(0x81), 1), int(0x03));
+        assert_eq!(BYTE.ror(&int(0x81), 1), int(0xC0));
+        assert_eq!(BYTE.rol(&int(0x12), 8), int(0x12));
+        assert_eq!(BYTE.ror(&int(0x12), 12), int(0x21));
+        assert_eq!(BYTE.ror(&int(0x12), 0), int(0x12));
+        assert_eq!(SIGNED_BYTE.rol(&int(0x40), 1), int(-128));
+    }
+
+    #[test]
+    fn finds_bit_patterns_of_numbers_that_fit() {
+        assert_eq!(BYTE.bit_pattern(&int(-1)), Some(int(0xFF)));
+        assert_eq!(SIGNED_BYTE.bit_pattern(&int(-128)), Some(int(0x80)));
+        assert_eq!(SIGNED_BYTE.bit_pattern(&int(255)), Some(int(0xFF)));
+        assert_eq!(BYTE.bit_pattern(&int(-129)), None);
+        assert_eq!(BYTE.bit_pattern(&int(256)), None);
+    }
+}
@@ -0,0 +1,41 @@
+//! A simple, yet succinct stack-based calculator.
+//!
+//! The `Calculator` owns the stack and can be driven either one `StackOp`
+//! at a time, or by handing it whole lines of input such as "2 3 +".
+//! Lines starting with "=" are infix expressions instead, such as "= (2 + 3) * 4".
+
+extern crate bigdecimal;
+extern crate num_bigint;
+extern crate num_complex;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
+
+mod angle;
+mod calculator;
+mod constant;
+mod error;
+mod format;
+mod infix;
+mod integer;
+mod list;
+mod matrix;
+mod op;
+mod parser;
+mod stats;
+mod unit;
+mod value;
+
+pub use angle::AngleMode;
+pub use calculator::{Calculator, Stack, DEFAULT_HISTORY_DEPTH};
+pub use constant::{Constant, CONSTANTS};
+pub use error::CalcError;
+pub use format::{DisplayFormat, Radix, MAX_DIGITS};
+pub use infix::to_rpn;
+pub use integer::MachineWord;
+pub use matrix::Matrix;
+pub use op::StackOp;
+pub use parser::{parse_string, tokenize, ParseError};
+pub use stats::Statistic;
+pub use unit::Unit;
+pub use value::{NumMode, Value, DEFAULT_PRECISION, MAX_PRECISION};
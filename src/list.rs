@@ -0,0 +1,153 @@
+use std::ops::{Add, Mul, Sub};
+
+use num_traits::ToPrimitive;
+
+use error::CalcError;
+use value::Value;
+
+/// The elements of a list, or an error if the value isn't one
+fn elements<'a>(op: &'static str, value: &'a Value) -> Result<&'a [Value], CalcError> {
+    match *value {
+        Value::List(ref xs) => Ok(xs),
+        ref value => Err(CalcError::DomainError { op, value: value.clone() }),
+    }
+}
+
+/// Multiplies two lists of the same length element by element, and adds up the products
+pub fn dot(a: &Value, b: &Value, precision: u64) -> Result<Value, CalcError> {
+    let (xs, ys) = (elements("dot", a)?, elements("dot", b)?);
+    if xs.len() != ys.len() {
+        return Err(CalcError::DimensionMismatch { op: "dot", left: a.shape(), right: b.shape() });
+    }
+
+    let products = xs.iter().zip(ys).map(|(x, y)| x.clone().mul(y.clone()));
+    Ok(products.fold(Value::from(0), |acc, xy| acc.add(xy).round_to(precision)))
+}
+
+/// The cross product of two lists of three elements
+pub fn cross(a: &Value, b: &Value, precision: u64) -> Result<Value, CalcError> {
+    let (xs, ys) = (elements("cross", a)?, elements("cross", b)?);
+    if xs.len() != 3 {
+        return Err(CalcError::DomainError { op: "cross", value: a.clone() });
+    }
+    if ys.len() != 3 {
+        return Err(CalcError::DomainError { op: "cross", value: b.clone() });
+    }
+
+    let term = |i: usize, j: usize| {
+        let (left, right) = (xs[i].clone().mul(ys[j].clone()), xs[j].clone().mul(ys[i].clone()));
+        left.sub(right).round_to(precision)
+    };
+    Ok(Value::List(vec![term(1, 2), term(2, 0), term(0, 1)]))
+}
+
+/// The length of a list seen as a vector, the square root of the sum of the squares
+/// of the elements of a matrix, or the absolute value of a single number.
+/// The elements have to be plain numbers.
+pub fn norm(a: &Value) -> Result<Value, CalcError> {
+    let length = |xs: &mut dyn Iterator<Item = &Value>| {
+        // an empty sum of floats is -0, so start from 0
+        let length = xs.fold(0.0, |sum, x| sum + x.clone().abs().to_f64().powi(2)).sqrt();
+        if length.is_nan() {
+            return Err(CalcError::DomainError { op: "norm", value: a.clone() });
+        }
+        Ok(Value::Float(length))
+    };
+    match *a {
+        Value::List(ref xs) => length(&mut xs.iter()),
+        Value::Matrix(ref m) => length(&mut m.elements()),
+        ref x => Ok(x.clone().abs()),
+    }
+}
+
+/// The number of elements in a list
+pub fn len(a: &Value) -> Result<Value, CalcError> {
+    Ok(Value::from(elements("len", a)?.len() as i64))
+}
+
+/// The element at a position in a list, counting from 0
+pub fn get(list: &Value, index: &Value) -> Result<Value, CalcError> {
+    let xs = elements("get", list)?;
+    index
+        .to_integer()
+        .and_then(|i| i.to_usize())
+        .and_then(|i| xs.get(i).cloned())
+        .ok_or_else(|| CalcError::DomainError { op: "get", value: index.clone() })
+}
+
+/// Joins two lists together, where a single number counts as a list of one
+pub fn concat(a: Value, b: Value) -> Result<Value, CalcError> {
+    let into_vec = |value| match value {
+        Value::List(xs) => Ok(xs),
+        Value::Matrix(_) => Err(CalcError::DomainError { op: "concat", value }),
+        x => Ok(vec![x]),
+    };
+
+    let mut xs = into_vec(a)?;
+    xs.extend(into_vec(b)?);
+    Ok(Value::List(xs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use value::NumMode;
+
+    fn parse(input: &str) -> Value {
+        Value::parse(input, NumMode::Float).unwrap()
+    }
+
+    #[test]
+    fn multiplies_vectors() {
+        assert_eq!(dot(&parse("[1 2 3]"), &parse("[4 5 6]"), 50), Ok(Value::from(32)));
+        assert_eq!(dot(&parse("[]"), &parse("[]"), 50), Ok(Value::from(0)));
+        assert_eq!(cross(&parse("[1 0 0]"), &parse("[0 1 0]"), 50), Ok(parse("[0 0 1]")));
+        assert_eq!(cross(&parse("[1 2 3]"), &parse("[4 5 6]"), 50), Ok(parse("[-3 6 -3]")));
+    }
+
+    #[test]
+    fn rejects_vectors_of_the_wrong_size() {
+        assert_eq!(
+            dot(&parse("[1 2 3]"), &parse("[1 2]"), 50),
+            Err(CalcError::DimensionMismatch { op: "dot", left: vec![3], right: vec![2] })
+        );
+        assert_eq!(
+            cross(&parse("[1 2]"), &parse("[1 2 3]"), 50),
+            Err(CalcError::DomainError { op: "cross", value: parse("[1 2]") })
+        );
+        assert_eq!(
+            dot(&Value::from(1), &parse("[1]"), 50),
+            Err(CalcError::DomainError { op: "dot", value: Value::from(1) })
+        );
+    }
+
+    #[test]
+    fn measures_vectors() {
+        assert_eq!(norm(&parse("[3 4]")), Ok(Value::Float(5.0)));
+        assert_eq!(norm(&parse("[[1 1][1 1]]")), Ok(Value::Float(2.0)));
+        assert_eq!(norm(&Value::from(-3)), Ok(Value::from(3)));
+        assert_eq!(norm(&parse("[]")), Ok(Value::Float(0.0)));
+        assert!(norm(&parse("[]")).unwrap().to_f64().is_sign_positive());
+        let quantities = parse("[3_m 4_m]");
+        assert_eq!(norm(&quantities), Err(CalcError::DomainError { op: "norm", value: quantities.clone() }));
+        assert_eq!(len(&parse("[1 2 3]")), Ok(Value::from(3)));
+        assert!(len(&Value::from(3)).is_err());
+    }
+
+    #[test]
+    fn gets_elements_by_position() {
+        let xs = parse("[10 20 30]");
+        assert_eq!(get(&xs, &Value::from(0)), Ok(Value::from(10)));
+        assert_eq!(get(&xs, &Value::Float(2.0)), Ok(Value::from(30)));
+        assert_eq!(get(&xs, &Value::from(3)), Err(CalcError::DomainError { op: "get", value: Value::from(3) }));
+        assert!(get(&xs, &Value::from(-1)).is_err());
+        assert!(get(&xs, &Value::Float(0.5)).is_err());
+    }
+
+    #[test]
+    fn joins_lists_and_numbers() {
+        assert_eq!(concat(parse("[1 2]"), parse("[3]")), Ok(parse("[1 2 3]")));
+        assert_eq!(concat(Value::from(1), Value::from(2)), Ok(parse("[1 2]")));
+        assert!(concat(parse("[[1]]"), Value::from(2)).is_err());
+    }
+}
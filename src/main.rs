@@ -1,56 +1,21 @@
-use std::io::{self, BufRead, Write};
-use std::ops::{Add, Sub, Mul, Div, Neg};
-use std::collections::VecDeque;
-use std::f64::consts;
-
-// We need a VecDeque because we need to also push to the back.
-// Using a regular vec would require dissolving the entire stack, 
-// just to push one element, and then add everything back.
-// This is more efficient.
-// A LinkedList could also be used, but the VecDeque has better locality.
-type Stack = VecDeque<f64>;
-
-const PHI: f64 = 1.61803398875;
-
-/// Every available operation in the calculator
-enum StackOp {
-    // binary operations
-    Add, // addition
-    Sub, // subtraction
-    Mul, // multiplication
-    Div, // division
-    Pow, // power
-    // unary operations
-    Sqrt, // square root
-    Neg,  // negation
-    Abs,  // absolute
-    Ln, Log, Lg, // log-e, log-10, and log-2
-    Sin, Asin,   // sin and its inverse
-    Cos, Acos,   // cos and its inverse
-    Tan, Atan,   // tan and its inverse
-    ToDeg, // converts a (radian) number to degrees
-    ToRad, // converts a (degree) number to radians
-    // stack operations
-    Sum,       // Sums the entire stack
-    Prod,      // Multiplies the entire stack
-    Pop,       // pops an item off the stack
-    Clear,     // clears the stack
-    Swap,      // swaps the two topmost elements
-    Rotate,    // pushes the front to the back
-    Duplicate, // duplicates the topmost element
-    // other
-    NoOp, // no operation (error case)
-    Num(f64), // a number
-}
+extern crate stack_calc;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::process;
+
+use stack_calc::{to_rpn, tokenize, CalcError, Calculator, NumMode, Radix, Value, CONSTANTS};
 
 /// Prints the list of available commands to the console
-fn print_help() -> StackOp {
+fn print_help() {
     println!("List of available commands: ");
     println!("help, ? -- print this help");
+    println!("quit, q -- Exits the calculator (as does ctrl+d)");
     println!("<number> -- Pushes a number to the stack");
-    println!("pi -- Pushes π onto the stack");
-    println!("e -- Pushes e onto the stack");
-    println!("phi -- Pushes the golden ratio onto the stack");
+    println!("= <expression> -- Evaluates an infix expression, such as \"= (2 + 3) * sin(pi / 4)\"");
+    println!("rpn <expression> -- Shows an infix expression in RPN without evaluating it");
+    println!("pi, e, c, ... -- Pushes a constant onto the stack; consts lists them all");
     println!("+, -, *, /, ^ -- Applies the respective binary operation");
     println!("sqrt -- Takes the square root of the last number");
     println!("neg -- Negates the last number");
@@ -58,201 +23,487 @@ fn print_help() -> StackOp {
     println!("ln -- Applies the natural log to the last number");
     println!("lg, log2 -- Applies the base-2 log to the last number");
     println!("log, log10 -- Applies the base-10 log to the last number");
-    println!("sin -- Applies the sine of the last number (in radians)");
-    println!("cos -- Applies the cosine of the last number (in radians)");
-    println!("tan -- Applies the tangent of the last number (in radians)");
+    println!("sin -- Applies the sine of the last number (in the angle mode)");
+    println!("cos -- Applies the cosine of the last number (in the angle mode)");
+    println!("tan -- Applies the tangent of the last number (in the angle mode)");
+    println!("deg, rad, grad -- Sets the angle mode to degrees, radians or gradians");
     println!("to deg -- Converts a number (in radians) to degrees");
     println!("to rad -- Converts a number (in degrees) to radians");
+    println!("->frac -- Converts a number to an exact fraction");
+    println!("->float -- Converts a number to a floating point number");
+    println!("div, mod -- Integer division (rounding down) and its remainder");
+    println!("gcd, lcm -- Greatest common divisor and least common multiple");
+    println!("fact, ! -- Takes the factorial of the last number");
+    println!("isprime -- Pushes 1 if the last number is a prime, 0 otherwise, for up to 4096 bits");
+    println!("0x<hex>, 0o<oct>, 0b<bin> -- Pushes an integer written in another base");
+    println!("hex, dec, oct, bin -- Displays integers in the respective base, as bit patterns of the word");
+    println!("and, or, xor, not -- Applies the respective bitwise operation");
+    println!("shl, shr, rol, ror -- Shifts or rotates by the last number of bits");
+    println!("word 8, word 16, word 32, word 64 -- Sets the word size of bitwise operations");
+    println!("signed, unsigned -- Sets how the results of bitwise operations are read");
+    println!("<re>+<im>i -- Pushes a complex number, such as 3+4i, 2i or i");
+    println!("re, im -- Takes the real or imaginary part of the last number");
+    println!("arg -- Takes the angle of the last number to the real axis");
+    println!("conj -- Takes the complex conjugate of the last number");
+    println!("->polar -- Splits a complex number into its length and angle");
+    println!("->rect -- Joins a length and an angle into a complex number");
+    println!("[1 2 3] -- Pushes a list; operations apply to each element, pairing up two lists");
+    println!("dot, cross -- The dot and cross product of two lists");
+    println!("norm -- The length of a list seen as a vector");
+    println!("len -- The number of elements in a list");
+    println!("get -- The element of a list at the last number, counting from 0");
+    println!("concat -- Joins two lists together");
+    println!("explode -- Pushes the elements of a list, followed by how many there are");
+    println!("collect -- Gathers the n numbers below n into a list");
+    println!("[[1 2][3 4]] -- Pushes a matrix; * multiplies matrices, + and - add them up element-wise");
+    println!("identity n -- Pushes the n by n identity matrix, for n up to 100");
+    println!("det, trace -- The determinant and the sum of the diagonal of a square matrix");
+    println!("inv -- The inverse of a square matrix");
+    println!("transpose -- Swaps the rows of a matrix with its columns");
+    println!("solve -- Finds x in Ax = b, with b a list or matrix on top of A");
+    println!("300_m, 9.8_m/s^2 -- Pushes a number with a unit; + and - need units of the same kind");
+    println!("convert <unit> -- Converts the last number to another unit of the same kind, as in convert ft");
     println!("sum -- Add the entire stack together");
     println!("prod -- Multiplies the entire stack together");
-    println!("pop -- Removes the topmost number");
+    println!("pop, drop -- Removes the topmost number");
     println!("clear -- Clears the stack");
     println!("swap -- Swaps the two topmost numbers");
     println!("rotate -- Moves the first number to the end of the stack");
-    StackOp::NoOp
+    println!("dup, copy -- Duplicates the topmost number");
+    println!("over, nip, tuck -- a b becomes a b a, b, and b a b respectively");
+    println!("rot, -rot -- a b c becomes b c a, and c a b respectively");
+    println!("2dup, 2drop, 2swap -- Duplicates, removes, or swaps the two topmost pairs of numbers");
+    println!("pick, roll -- Copies or moves the nth number below n to the top, counting from 0");
+    println!("depth -- Pushes the number of numbers on the stack");
+    println!("dupn -- Duplicates the n numbers below n");
+    println!("count, mean, median, mode, min, max, range -- Summarises the entire stack");
+    println!("var, stdev -- The sample variance and standard deviation of the entire stack");
+    println!("pvar, pstdev -- The population variance and standard deviation of the entire stack");
+    println!("percentile -- The percentile of the entire stack given by the last number");
+    println!("n<statistic> -- Summarises only the n numbers below n instead, e.g. \"3 nmean\"");
+    println!("linreg -- Fits a line through the stack as x y pairs, pushing its slope, intercept and r²");
+    println!("predict -- Evaluates the line fitted by linreg at the last number");
+    println!("mode decimal -- Enters numbers as exact decimals");
+    println!("mode rational, mode exact -- Enters numbers as exact fractions, such as 1/3");
+    println!("mode float -- Enters numbers as floating point numbers");
+    println!("std -- Displays numbers with as many digits as they need");
+    println!("fix <digits> -- Displays numbers with a fixed number of decimals, up to 100");
+    println!("sci <digits> -- Displays numbers in scientific notation");
+    println!("eng <digits> -- Displays numbers in engineering notation, with exponents of 3, 6, 9...");
+    println!("show <levels>, show all -- Displays only the topmost levels of the stack, or all of them");
+    println!("precision <digits> -- Sets the significant digits kept when dividing decimals, up to 1000");
+    println!("undo, u -- Reverts the last operation, including changes to variables and settings");
+    println!("redo -- Reapplies the last reverted operation");
+    println!(": <name> <body> ; -- Defines a new word, e.g. \": sq dup * ;\"");
+    println!("words -- Lists the user-defined words");
+    println!("sto <name> -- Pops the topmost number into a variable");
+    println!("rcl <name>, <name> -- Pushes the value of a variable");
+    println!("vars -- Lists the variables");
+    println!("consts -- Lists the constants, such as pi and c, with their values");
 }
 
-/// Parses a string and returns a stack-operator
-fn parse_string(input: &str) -> StackOp {
-    use StackOp::*;
-
-    match input.trim() {
-        // binary operations
-        "+" | "add" => Add,
-        "-" | "sub" | "subtract" => Sub,
-        "*" | "mul" | "multiply" => Mul,
-        "/" | "div" | "divide" => Div,
-        "^" | "pow" | "power" => Pow,
-        // unary operations
-        "abs" | "absolute" => Abs,
-        "sqrt" | "root" => Sqrt,
-        "neg" | "negate" | "~" => Neg,
-        "ln" | "loge" => Ln,
-        "log" | "log10" => Log,
-        "lg" | "log2" => Lg,
-        "sin" => Sin,
-        "asin" | "sin^-1" => Asin,
-        "cos" => Cos,
-        "acos" | "cos^-1" => Acos,
-        "tan" => Tan,
-        "atan" | "tan^-1" => Atan,
-        "deg" | "to deg" => ToDeg,
-        "rad" | "to rad" => ToRad,
-        // constants
-        "pi" | "π" => Num(consts::PI),
-        "e" => Num(consts::E),
-        "phi" | "φ" | "ϕ" => Num(PHI),
-        // stack operations
-        "sum" => Sum,
-        "prod" => Prod,
-        "pop" => Pop,
-        "clear" | "cls" => Clear,
-        "swap" => Swap,
-        "rotate" | "rot" => Rotate,
-        "copy" | "clone" | "duplicate" => Duplicate,
-        // other
-        "help" | "?" => print_help(),
-        "quit" | "q" | "end" => {
-            println!("To quit, press ctrl+c");
-            NoOp
-        },
-        // number
-        str => {
-            if let Ok(num) = str.parse::<f64>() {
-                Num(num)
-            } else {
-                println!("Error! Couldn't parse {}", str);
-                NoOp
-            }
-        }
+/// Prints every user-defined word along with its definition
+fn print_words(calc: &Calculator) {
+    let mut any = false;
+    for (name, body) in calc.words() {
+        println!(": {} {} ;", name, body);
+        any = true;
+    }
+    if !any {
+        println!("No words have been defined yet");
     }
 }
 
-/// Prompts the user for an input from the console.
-fn get_input() -> io::Result<StackOp> {
+/// Prints every variable along with its value
+fn print_vars(calc: &Calculator) {
+    let mut any = false;
+    for (name, num) in calc.vars() {
+        println!("{} = {}", name, format_num(calc, num));
+        any = true;
+    }
+    if !any {
+        println!("No variables have been stored yet");
+    }
+}
+
+/// Prints every constant along with its value and what it is
+fn print_consts() {
+    for constant in CONSTANTS {
+        println!("{} = {} -- {}", constant.names.join(", "), constant.value, constant.description);
+    }
+}
+
+/// Prompts the user for an input, showing the angle mode in the prompt.
+/// Returns None once the input has been closed, such as with ctrl+d.
+fn get_input<R: BufRead, W: Write>(calc: &Calculator, input: &mut R, output: &mut W) -> io::Result<Option<String>> {
     let mut buff = String::new();
-    let stdin = io::stdin();
 
-    print!("> ");
-    io::stdout().flush()?;
-    stdin.lock().read_line(&mut buff)?;
-    buff = buff.to_lowercase(); // ensure lowercase
+    write!(output, "{}> ", calc.angle())?;
+    output.flush()?;
+    if input.read_line(&mut buff)? == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(buff.to_lowercase())) // ensure lowercase
+}
+
+/// Translates an infix expression into RPN, as printed by the rpn command
+fn rpn(expr: &str) -> Result<String, CalcError> {
+    Ok(to_rpn(expr)?.join(" "))
+}
 
-    Ok(parse_string(&buff))
+/// Whether to keep going after a line has been evaluated
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flow {
+    Continue,
+    Quit,
 }
 
-/// Applies a binary operation if the stack has enough elements.
-/// If not, nothing happens.
-/// NB The top of the stack holds the SECOND operator, not the first
-/// So if we push 2 1 - the operation becomes 2 - 1, not 1 - 2
-fn eval_binop<F>(stack: &mut Stack, fun: F)
-where
-    F: FnOnce(f64, f64) -> f64,
-{
-    if stack.len() >= 2 {
-        // we know it's safe to unwrap, because the stack has at least 2 numbers
-        let a = stack.pop_back().unwrap();
-        let b = stack.pop_back().unwrap();
-        stack.push_back(fun(b, a));
+/// Runs every token on the line through the calculator,
+/// handling the commands that only make sense in the REPL along the way.
+/// A line starting with "=" is evaluated as an infix expression instead,
+/// and a line starting with "rpn" prints the RPN translation of one.
+/// The rest of the line is skipped as soon as a token fails.
+/// Word definitions are left to the calculator, even when they span several lines.
+fn run_line(calc: &mut Calculator, line: &str) -> Result<Flow, CalcError> {
+    if !calc.is_defining() {
+        let line = line.trim_start();
+        if let Some(expr) = line.strip_prefix('=') {
+            calc.eval_infix(expr)?;
+            return Ok(Flow::Continue);
+        }
+        if line.split_whitespace().next() == Some("rpn") {
+            println!("{}", rpn(&line["rpn".len()..])?);
+            return Ok(Flow::Continue);
+        }
+    }
+
+    for token in tokenize(line) {
+        if calc.is_defining() {
+            calc.eval_token(&token)?;
+            continue;
+        }
+
+        match token.as_str() {
+            "help" | "?" => print_help(),
+            "quit" | "q" | "end" => return Ok(Flow::Quit),
+            "words" => print_words(calc),
+            "vars" => print_vars(calc),
+            "consts" => print_consts(),
+            _ => calc.eval_token(&token)?,
+        }
     }
+    Ok(Flow::Continue)
+}
+
+/// Where the calculator takes its input from
+#[derive(Debug, PartialEq)]
+enum Input {
+    Interactive,    // prompts the user line by line
+    Script(String), // evaluates the given text without prompting
+}
+
+/// What was asked for on the command line
+struct Options {
+    input: Option<Input>, // None unless -e or -f was given
+    mode: NumMode,
 }
 
-/// Applies a unary operation if the stack has enough elements.
-/// If not, nothing happens.
-fn eval_unop<F>(stack: &mut Stack, fun: F)
-where
-    F: FnOnce(f64) -> f64,
-{
-    if let Some(a) = stack.pop_back() {
-        stack.push_back(fun(a));
+/// Prints how to invoke the calculator from the command line
+fn print_usage() {
+    eprintln!("Usage: stack_calc [-d | -r] [-e <expression> | -f <file>]");
+    eprintln!("  -d, --decimal            Enters numbers as exact decimals instead of floats");
+    eprintln!("  -r, --rational           Enters numbers as exact fractions instead of floats");
+    eprintln!("  -e, --eval <expression>  Evaluates the expression and prints the stack");
+    eprintln!("  -f, --file <file>        Evaluates the file and prints the stack");
+    eprintln!("Without -e or -f, piped input is evaluated as a script,");
+    eprintln!("otherwise the calculator starts interactively.");
+}
+
+/// Works out what to do based on the command line arguments, not counting the program name.
+/// Returns None if the arguments don't make sense.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> io::Result<Option<Options>> {
+    let mut args = args.into_iter();
+    let mut mode = NumMode::Float;
+    let mut input = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-d" | "--decimal" => mode = NumMode::Decimal,
+            "-r" | "--rational" => mode = NumMode::Rational,
+            "-e" | "--eval" if input.is_none() => match args.next() {
+                Some(expr) => input = Some(Input::Script(expr)),
+                None => return Ok(None),
+            },
+            "-f" | "--file" if input.is_none() => match args.next() {
+                Some(file) => input = Some(Input::Script(fs::read_to_string(file)?)),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        }
     }
+
+    Ok(Some(Options { input, mode }))
 }
 
-// Folds the stack over fun, then pushes the result.
-fn eval_stackop<F>(stack: &mut Stack, start: f64, fun: F)
-where
-    F: FnMut(f64, &f64) -> f64,
-{
-    let result = stack.iter().fold(start, fun);
-    stack.clear();
-    stack.push_back(result);
+/// The input to use when neither -e nor -f was given:
+/// piped input is evaluated as a script, otherwise the calculator prompts the user
+fn default_input() -> io::Result<Input> {
+    if io::stdin().is_terminal() {
+        return Ok(Input::Interactive);
+    }
+    let mut script = String::new();
+    io::stdin().read_to_string(&mut script)?;
+    Ok(Input::Script(script))
 }
 
-/// Swaps the two topmost elements of the stack
-fn swap(stack: &mut Stack) {
-    if stack.len() >= 2 {
-        let a = stack.pop_back().unwrap();
-        let b = stack.pop_back().unwrap();
-        stack.push_back(a);
-        stack.push_back(b);
+/// Formats a number, with integers in the calculator's base unless it's decimal,
+/// and everything else in its display format
+fn format_num(calc: &Calculator, num: &Value) -> String {
+    match *num {
+        Value::Int(ref n) if calc.radix() != Radix::Dec => calc.radix().format(n, calc.word()),
+        Value::List(ref xs) => {
+            let xs: Vec<String> = xs.iter().map(|x| format_num(calc, x)).collect();
+            format!("[{}]", xs.join(" "))
+        }
+        Value::Matrix(ref m) => {
+            let rows: Vec<String> = m.iter().map(|row| format_num(calc, &Value::List(row.clone()))).collect();
+            format!("[{}]", rows.concat())
+        }
+        Value::Quantity(ref x, ref unit) => format!("{}_{}", format_num(calc, x), unit),
+        ref num => calc.format().format(num),
     }
 }
 
-/// Moves the topmost element to the bottom
-fn rotate(stack: &mut Stack) {
-    if let Some(num) = stack.pop_back() {
-        stack.push_front(num); // Important! This must be the opposite of the pop
+/// Formats the stack vertically, one level per line with the top of the stack at the bottom,
+/// as in "2: 10" above "1: 42". Both the levels and the numbers are right-aligned.
+/// Levels that aren't shown are counted on the first line instead.
+fn format_stack(calc: &Calculator) -> String {
+    let stack = calc.stack();
+    let shown = calc.levels().map_or(stack.len(), |levels| levels.min(stack.len()));
+    let nums: Vec<String> = stack
+        .iter()
+        .skip(stack.len() - shown)
+        .map(|num| format_num(calc, num))
+        .collect();
+
+    let level_width = shown.to_string().len();
+    let num_width = nums.iter().map(|num| num.chars().count()).max().unwrap_or(0);
+
+    let mut lines = Vec::new();
+    if shown < stack.len() {
+        lines.push(format!("({} more)", stack.len() - shown));
+    }
+    for (i, num) in nums.iter().enumerate() {
+        let level = shown - i;
+        lines.push(format!("{:>lw$}: {:>nw$}", level, num, lw = level_width, nw = num_width));
     }
+
+    lines.join("\n")
 }
 
-/// Duplicates the topmost element of the stack
-fn duplicate(stack: &mut Stack) {
-    if let Some(&num) = stack.back() {
-        stack.push_back(num);
+/// Evaluates a script line by line, reporting every failing line on stderr.
+/// A quit command ends the script early.
+/// Returns whether the whole script evaluated without errors.
+fn run_script(calc: &mut Calculator, script: &str) -> bool {
+    let mut success = true;
+
+    for (number, line) in script.lines().enumerate() {
+        match run_line(calc, &line.to_lowercase()) {
+            Ok(Flow::Continue) => (),
+            Ok(Flow::Quit) => break,
+            Err(err) => {
+                eprintln!("Error on line {}! {}", number + 1, err);
+                success = false;
+            }
+        }
+    }
+
+    if calc.is_defining() {
+        eprintln!("Error! The script ends in the middle of a word definition");
+        success = false;
     }
+
+    success
 }
 
-/// Determines what to do given a StackOp, and applies its effect to the stack
-fn eval(stack: &mut Stack, last_op: StackOp) {
-    use StackOp::*;
-
-    match last_op {
-        // binary operators
-        Add => eval_binop(stack, f64::add),
-        Sub => eval_binop(stack, f64::sub),
-        Mul => eval_binop(stack, f64::mul),
-        Div => eval_binop(stack, f64::div),
-        Pow => eval_binop(stack, f64::powf),
-        // unary operators
-        Sqrt  => eval_unop(stack, f64::sqrt),
-        Abs   => eval_unop(stack, f64::abs),
-        Neg   => eval_unop(stack, f64::neg),
-        Ln    => eval_unop(stack, f64::ln),
-        Lg    => eval_unop(stack, f64::log2),
-        Log   => eval_unop(stack, f64::log10),
-        Sin   => eval_unop(stack, f64::sin),
-        Asin  => eval_unop(stack, f64::asin),
-        Cos   => eval_unop(stack, f64::cos),
-        Acos  => eval_unop(stack, f64::acos),
-        Tan   => eval_unop(stack, f64::tan),
-        Atan  => eval_unop(stack, f64::atan),
-        ToDeg => eval_unop(stack, f64::to_degrees),
-        ToRad => eval_unop(stack, f64::to_radians),
-        // stack operations
-        Sum       => eval_stackop(stack, 0.0, |acc, x| acc + x),
-        Prod      => eval_stackop(stack, 1.0, |acc, x| acc * x),
-        Pop       => { stack.pop_back(); }, // brackets required to ignore result of pop_back
-        Clear     => stack.clear(),
-        Swap      => swap(stack),
-        Rotate    => rotate(stack),
-        Duplicate => duplicate(stack),
-        // number
-        Num(n) => stack.push_back(n),
-        // other
-        NoOp => return, // do nothing
+/// Prompts for input until the user quits or closes the input.
+/// The stack and errors are written to the output after every line.
+fn run_interactive<R: BufRead, W: Write>(calc: &mut Calculator, mut input: R, mut output: W) -> io::Result<()> {
+    writeln!(output, "Welcome to the stack calculator!")?;
+    writeln!(output, "Type \"help\" and hit return to view available commands.")?;
+    loop {
+        let line = match get_input(calc, &mut input, &mut output)? {
+            Some(line) => line,
+            None => {
+                writeln!(output)?; // leave the shell prompt on a line of its own
+                return Ok(());
+            }
+        };
+
+        match run_line(calc, &line) {
+            Ok(Flow::Continue) => (),
+            Ok(Flow::Quit) => return Ok(()),
+            Err(err) => writeln!(output, "Error! {}", err)?,
+        }
+
+        if !calc.stack().is_empty() {
+            writeln!(output, "{}", format_stack(calc))?;
+        }
     }
 }
 
 fn main() -> io::Result<()> {
-    println!("Welcome to the stack calculator!");
-    println!("Type \"help\" and hit return to view available commands.");
-    let mut stack = VecDeque::new();
-    loop {
-        let input = get_input()?;
-        eval(&mut stack, input);
+    let mut calc = Calculator::new();
+
+    let options = parse_args(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("Error! {}", err);
+        process::exit(2);
+    });
+    let options = options.unwrap_or_else(|| {
+        print_usage();
+        process::exit(2);
+    });
+    calc.set_mode(options.mode);
+
+    let input = match options.input {
+        Some(input) => input,
+        None => default_input()?,
+    };
+    match input {
+        Input::Interactive => run_interactive(&mut calc, io::stdin().lock(), io::stdout()),
+        Input::Script(script) => {
+            let success = run_script(&mut calc, &script);
+            for num in calc.stack() {
+                println!("{}", format_num(&calc, num));
+            }
+            if !success {
+                process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// The stack as it's printed after a script, bottom first
+    fn show(calc: &Calculator) -> String {
+        let nums: Vec<String> = calc.stack().iter().map(|num| format_num(calc, num)).collect();
+        nums.join(" ")
+    }
+
+    /// A calculator that has evaluated the input, which has to succeed
+    fn calculator(input: &str) -> Calculator {
+        let mut calc = Calculator::new();
+        calc.eval_str(input).unwrap();
+        calc
+    }
+
+    /// Runs the calculator interactively on the input, returning everything it printed
+    fn interact(calc: &mut Calculator, input: &str) -> String {
+        let mut output = Vec::new();
+        run_interactive(calc, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn reads_the_script_and_mode_from_the_arguments() {
+        let options = parse_args(args(&["-e", "1 2 +"])).unwrap().unwrap();
+        assert_eq!(options.input, Some(Input::Script("1 2 +".to_string())));
+        assert_eq!(options.mode, NumMode::Float);
+
+        let options = parse_args(args(&["--rational", "-d"])).unwrap().unwrap();
+        assert_eq!(options.input, None);
+        assert_eq!(options.mode, NumMode::Decimal);
+
+        assert!(parse_args(args(&["-e"])).unwrap().is_none());
+        assert!(parse_args(args(&["-e", "1", "-e", "2"])).unwrap().is_none());
+        assert!(parse_args(args(&["--bogus"])).unwrap().is_none());
+        assert!(parse_args(args(&["-f", "/no/such/file"])).is_err());
+    }
+
+    #[test]
+    fn reports_whether_a_script_succeeded() {
+        let mut calc = Calculator::new();
+        assert!(run_script(&mut calc, "1 2 +\n\n3 *"));
+        assert_eq!(show(&calc), "9");
 
-        if stack.len() >= 1 {
-            println!("Stack: {:.2?}", stack);
+        // a failing line is reported, but the rest of the script still runs
+        let mut calc = Calculator::new();
+        assert!(!run_script(&mut calc, "1 2\nbogus 3\n+"));
+        assert_eq!(show(&calc), "3");
+
+        let mut calc = Calculator::new();
+        assert!(!run_script(&mut calc, ": sq dup *"));
+
+        let mut calc = Calculator::new();
+        assert!(run_script(&mut calc, "1 2 SWAP\nquit\nbogus"));
+        assert_eq!(show(&calc), "2 1");
+    }
+
+    #[test]
+    fn quits_on_any_of_its_names() {
+        for quit in &["quit", "q", "end"] {
+            let mut calc = Calculator::new();
+            assert_eq!(run_line(&mut calc, &format!("1 {} 2", quit)), Ok(Flow::Quit));
+            assert_eq!(show(&calc), "1");
         }
+        assert_eq!(run_line(&mut Calculator::new(), "1 2"), Ok(Flow::Continue));
+    }
+
+    #[test]
+    fn stops_at_quit_or_the_end_of_the_input() {
+        let mut calc = Calculator::new();
+        interact(&mut calc, "1 2\nQUIT\n3\n");
+        assert_eq!(show(&calc), "1 2");
+
+        let mut calc = Calculator::new();
+        let output = interact(&mut calc, "1 2 +\nbogus");
+        assert_eq!(show(&calc), "3");
+        assert!(output.contains("Error! Couldn't parse bogus\n"));
+        // the shell prompt is left on a line of its own
+        assert!(output.ends_with("rad> \n"));
+    }
+
+    #[test]
+    fn formats_integers_in_the_base_or_else_like_other_numbers() {
+        assert_eq!(show(&calculator("fix 2 255 1.5")), "255.00 1.50");
+        assert_eq!(show(&calculator("fix 2 hex 255 1.5")), "0xFF 1.50");
+        assert_eq!(show(&calculator("sci 2 [1 2.5] 3_m")), "[1.00e0 2.50e0] 3.00e0_m");
+    }
+
+    #[test]
+    fn labels_each_level_of_the_stack() {
+        assert_eq!(format_stack(&calculator("1 22 333")), "3:   1\n2:  22\n1: 333");
+        assert_eq!(format_stack(&calculator("hex 255 -1.5")), "2: 0xFF\n1: -1.5");
+        assert_eq!(format_stack(&Calculator::new()), "");
+
+        let stack = format_stack(&calculator("1 2 3 4 5 6 7 8 9 10"));
+        assert_eq!(stack.lines().next(), Some("10:  1"));
+        assert_eq!(stack.lines().last(), Some(" 1: 10"));
+    }
+
+    #[test]
+    fn shows_only_the_topmost_levels() {
+        assert_eq!(format_stack(&calculator("1 22 333 show 2")), "(1 more)\n2:  22\n1: 333");
+        assert_eq!(format_stack(&calculator("1 2 show 5")), "2: 1\n1: 2");
+    }
+
+    #[test]
+    fn translates_expressions_without_evaluating_them() {
+        assert_eq!(rpn("(2 + 3) * sin(pi / 4)"), Ok("2 3 + pi 4 / sin *".to_string()));
+
+        let mut calc = calculator("1");
+        assert_eq!(run_line(&mut calc, "rpn 2 + 3"), Ok(Flow::Continue));
+        assert_eq!(show(&calc), "1");
+        assert!(matches!(run_line(&mut calc, "  rpn 2 +"), Err(CalcError::InvalidExpression(_))));
+        assert_eq!(show(&calc), "1");
     }
 }
@@ -0,0 +1,396 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Sub};
+use std::slice;
+
+use error::CalcError;
+use value::{NumMode, Value};
+
+/// The biggest identity matrix that can be asked for, which already has 10,000 elements
+pub const MAX_IDENTITY: usize = 100;
+
+/// A matrix of numbers, such as [[1 2][3 4]].
+/// It always has at least one row and one column, and every row is as long as the others.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: Vec<Vec<Value>>,
+}
+
+impl Matrix {
+    /// Creates a matrix from its rows, which have to be non-empty and of the same length
+    pub fn new(rows: Vec<Vec<Value>>) -> Option<Matrix> {
+        let cols = rows.first()?.len();
+        if cols == 0 || rows.iter().any(|row| row.len() != cols) {
+            return None;
+        }
+        Some(Matrix { rows })
+    }
+
+    /// The n by n matrix with ones on the diagonal and zeros elsewhere
+    pub fn identity(n: usize) -> Matrix {
+        let rows = (0..n)
+            .map(|i| (0..n).map(|j| Value::from((i == j) as i64)).collect())
+            .collect();
+        Matrix { rows }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.rows[0].len()
+    }
+
+    pub fn is_square(&self) -> bool {
+        self.rows() == self.cols()
+    }
+
+    /// Iterates over the rows
+    pub fn iter(&self) -> slice::Iter<'_, Vec<Value>> {
+        self.rows.iter()
+    }
+
+    /// Iterates over every element, row by row
+    pub fn elements(&self) -> impl Iterator<Item = &Value> {
+        self.rows.iter().flatten()
+    }
+
+    /// Applies a function to every element
+    pub fn map<F>(self, fun: F) -> Matrix
+    where
+        F: Fn(Value) -> Value,
+    {
+        let rows = self.rows.into_iter().map(|row| row.into_iter().map(&fun).collect()).collect();
+        Matrix { rows }
+    }
+
+    /// Combines the elements at the same positions of two matrices of the same shape
+    pub fn zip_with<F>(self, other: Matrix, fun: F) -> Matrix
+    where
+        F: Fn(Value, Value) -> Value,
+    {
+        let rows = self
+            .rows
+            .into_iter()
+            .zip(other.rows)
+            .map(|(xs, ys)| xs.into_iter().zip(ys).map(|(x, y)| fun(x, y)).collect())
+            .collect();
+        Matrix { rows }
+    }
+
+    /// The matrix product, which needs as many columns on the left as there are rows on the right
+    pub fn product(&self, other: &Matrix) -> Matrix {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| (0..other.cols()).map(|j| dot(row.iter(), other.rows.iter().map(|r| &r[j]))).collect())
+            .collect();
+        Matrix { rows }
+    }
+
+    /// Multiplies a column vector, which needs as many elements as there are columns
+    pub fn apply(&self, xs: &[Value]) -> Vec<Value> {
+        self.rows.iter().map(|row| dot(row.iter(), xs.iter())).collect()
+    }
+
+    /// Multiplies a row vector from the left, which needs as many elements as there are rows
+    pub fn apply_left(&self, xs: &[Value]) -> Vec<Value> {
+        (0..self.cols()).map(|j| dot(xs.iter(), self.rows.iter().map(|r| &r[j]))).collect()
+    }
+
+    /// Swaps the rows with the columns
+    pub fn transpose(&self) -> Matrix {
+        let rows = (0..self.cols())
+            .map(|j| self.rows.iter().map(|row| row[j].clone()).collect())
+            .collect();
+        Matrix { rows }
+    }
+
+    /// The sum of the diagonal, for square matrices only
+    pub fn trace(&self) -> Option<Value> {
+        if !self.is_square() {
+            return None;
+        }
+        Some((0..self.rows()).fold(Value::from(0), |acc, i| acc.add(self.rows[i][i].clone())))
+    }
+
+    /// The determinant, for square matrices only.
+    /// Found by fraction-free elimination, so integer matrices have an exact integer determinant.
+    pub fn det(&self, mode: NumMode, precision: u64) -> Option<Value> {
+        if !self.is_square() {
+            return None;
+        }
+
+        let n = self.rows();
+        let mut m = self.rows.clone();
+        let mut negate = false;
+        let mut previous = Value::from(1);
+
+        for k in 0..n {
+            let pivot = pivot_row(&m, k);
+            if m[pivot][k].is_zero() {
+                return Some(Value::from(0));
+            }
+            if pivot != k {
+                m.swap(pivot, k);
+                negate = !negate;
+            }
+
+            for i in k + 1..n {
+                for j in k + 1..n {
+                    let cross = m[i][j].clone().mul(m[k][k].clone()).sub(m[i][k].clone().mul(m[k][j].clone()));
+                    m[i][j] = cross.div(previous.clone(), NumMode::Rational, precision);
+                }
+            }
+            previous = m[k][k].clone();
+        }
+
+        let det = if negate { -previous } else { previous };
+        Some(settle(det, mode, precision))
+    }
+
+    /// The inverse, for square matrices that aren't singular only
+    pub fn inverse(&self, mode: NumMode, precision: u64) -> Option<Matrix> {
+        if !self.is_square() {
+            return None;
+        }
+        self.solve(&Matrix::identity(self.rows()), mode, precision)
+    }
+
+    /// Finds x in Ax = b, where A is this matrix, which has to be square and not singular,
+    /// and b has as many rows as A. Solves for every column of b at once,
+    /// by Gauss-Jordan elimination.
+    pub fn solve(&self, b: &Matrix, mode: NumMode, precision: u64) -> Option<Matrix> {
+        if !self.is_square() || b.rows() != self.rows() {
+            return None;
+        }
+
+        let n = self.rows();
+        // the augmented matrix [A | b]
+        let mut m: Vec<Vec<Value>> = self
+            .rows
+            .iter()
+            .zip(&b.rows)
+            .map(|(a, b)| a.iter().chain(b).cloned().collect())
+            .collect();
+
+        for k in 0..n {
+            let pivot = pivot_row(&m, k);
+            if m[pivot][k].is_zero() {
+                return None;
+            }
+            m.swap(pivot, k);
+
+            let divisor = m[k][k].clone();
+            let row: Vec<Value> = m[k]
+                .iter()
+                .map(|x| x.clone().div(divisor.clone(), NumMode::Rational, precision))
+                .collect();
+            for (i, other) in m.iter_mut().enumerate() {
+                if i == k || other[k].is_zero() {
+                    continue;
+                }
+                let factor = other[k].clone();
+                for (x, y) in other.iter_mut().zip(&row) {
+                    *x = x.clone().sub(factor.clone().mul(y.clone())).round_to(precision);
+                }
+            }
+            m[k] = row;
+        }
+
+        let rows = m
+            .into_iter()
+            .map(|row| row.into_iter().skip(n).map(|x| settle(x, mode, precision)).collect())
+            .collect();
+        Some(Matrix { rows })
+    }
+}
+
+/// Adds up the products of two sequences of numbers
+fn dot<'a, I, J>(xs: I, ys: J) -> Value
+where
+    I: Iterator<Item = &'a Value>,
+    J: Iterator<Item = &'a Value>,
+{
+    xs.zip(ys).fold(Value::from(0), |acc, (x, y)| acc.add(x.clone().mul(y.clone())))
+}
+
+/// The row, from k down, with the largest number in column k,
+/// which keeps the rounding errors of floats small
+fn pivot_row(m: &[Vec<Value>], k: usize) -> usize {
+    (k..m.len())
+        .max_by(|&i, &j| {
+            let (a, b) = (m[i][k].clone().abs(), m[j][k].clone().abs());
+            // prefer the upper row on a tie
+            a.compare(&b).unwrap_or(Ordering::Equal).then(j.cmp(&i))
+        })
+        .unwrap_or(k)
+}
+
+/// Elimination divides in rational mode, so nothing is lost along the way.
+/// This gives the fractions it ends up with the representation the mode asks for.
+fn settle(x: Value, mode: NumMode, precision: u64) -> Value {
+    match x {
+        Value::Rational(x) => Value::inexact(x, mode, precision),
+        x => x,
+    }
+}
+
+/// The matrix a value holds, or an error if it isn't one
+fn matrix<'a>(op: &'static str, value: &'a Value) -> Result<&'a Matrix, CalcError> {
+    match *value {
+        Value::Matrix(ref m) => Ok(m),
+        ref value => Err(CalcError::DomainError { op, value: value.clone() }),
+    }
+}
+
+/// The matrix a value holds, or an error if it isn't a square one
+fn square<'a>(op: &'static str, value: &'a Value) -> Result<&'a Matrix, CalcError> {
+    match matrix(op, value)? {
+        m if m.is_square() => Ok(m),
+        _ => Err(CalcError::DomainError { op, value: value.clone() }),
+    }
+}
+
+/// The determinant of a square matrix
+pub fn det(a: &Value, mode: NumMode, precision: u64) -> Result<Value, CalcError> {
+    Ok(square("det", a)?.det(mode, precision).expect("square matrices have a determinant"))
+}
+
+/// The inverse of a square matrix, which can't be singular
+pub fn inverse(a: &Value, mode: NumMode, precision: u64) -> Result<Value, CalcError> {
+    square("inv", a)?
+        .inverse(mode, precision)
+        .map(Value::Matrix)
+        .ok_or_else(|| CalcError::DomainError { op: "inv", value: a.clone() })
+}
+
+/// Swaps the rows of a matrix with its columns
+pub fn transpose(a: &Value) -> Result<Value, CalcError> {
+    Ok(Value::Matrix(matrix("transpose", a)?.transpose()))
+}
+
+/// The sum of the diagonal of a square matrix
+pub fn trace(a: &Value) -> Result<Value, CalcError> {
+    Ok(square("trace", a)?.trace().expect("square matrices have a trace"))
+}
+
+/// Finds x in Ax = b, where A is a square matrix that isn't singular, and b is either a list
+/// with an element for every row of A, giving a list, or a matrix with as many rows, giving a matrix
+pub fn solve(a: &Value, b: &Value, mode: NumMode, precision: u64) -> Result<Value, CalcError> {
+    let m = square("solve", a)?;
+    let (rhs, is_list) = match *b {
+        Value::List(ref xs) => (Matrix::new(xs.iter().map(|x| vec![x.clone()]).collect()), true),
+        Value::Matrix(ref rhs) => (Some(rhs.clone()), false),
+        ref b => return Err(CalcError::DomainError { op: "solve", value: b.clone() }),
+    };
+    let rhs = match rhs {
+        Some(rhs) if rhs.rows() == m.rows() => rhs,
+        _ => return Err(CalcError::DimensionMismatch { op: "solve", left: a.shape(), right: b.shape() }),
+    };
+
+    let x = m
+        .solve(&rhs, mode, precision)
+        .ok_or_else(|| CalcError::DomainError { op: "solve", value: a.clone() })?;
+    if is_list {
+        Ok(Value::List(x.rows.into_iter().flatten().collect()))
+    } else {
+        Ok(Value::Matrix(x))
+    }
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for row in &self.rows {
+            Value::List(row.clone()).fmt(f)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str, mode: NumMode) -> Value {
+        Value::parse(input, mode).unwrap()
+    }
+
+    fn matrix(input: &str) -> Matrix {
+        match parse(input, NumMode::Rational) {
+            Value::Matrix(m) => m,
+            other => panic!("{} isn't a matrix", other),
+        }
+    }
+
+    #[test]
+    fn needs_rows_of_the_same_length() {
+        assert_eq!(Matrix::new(Vec::new()), None);
+        assert_eq!(Matrix::new(vec![Vec::new()]), None);
+        assert_eq!(Matrix::new(vec![vec![Value::from(1)], vec![]]), None);
+        assert_eq!(Matrix::identity(2), matrix("[[1 0][0 1]]"));
+    }
+
+    #[test]
+    fn multiplies_matrices_and_vectors() {
+        let a = matrix("[[1 2][3 4]]");
+        assert_eq!(a.product(&matrix("[[5][6]]")), matrix("[[17][39]]"));
+        assert_eq!(a.product(&Matrix::identity(2)), a);
+        assert_eq!(a.apply(&[Value::from(1), Value::from(1)]), vec![Value::from(3), Value::from(7)]);
+        assert_eq!(a.apply_left(&[Value::from(1), Value::from(1)]), vec![Value::from(4), Value::from(6)]);
+        assert_eq!(matrix("[[1 2 3]]").transpose(), matrix("[[1][2][3]]"));
+        assert_eq!(a.trace(), Some(Value::from(5)));
+        assert_eq!(matrix("[[1 2]]").trace(), None);
+    }
+
+    #[test]
+    fn finds_exact_determinants() {
+        assert_eq!(matrix("[[1 2][3 4]]").det(NumMode::Float, 50), Some(Value::from(-2)));
+        assert_eq!(matrix("[[2 0 1][1 3 2][1 1 2]]").det(NumMode::Float, 50), Some(Value::from(6)));
+        // the first pivot is zero, so rows have to be swapped
+        assert_eq!(matrix("[[0 1][1 0]]").det(NumMode::Float, 50), Some(Value::from(-1)));
+        assert_eq!(matrix("[[1 2][2 4]]").det(NumMode::Float, 50), Some(Value::from(0)));
+        assert_eq!(matrix("[[1/2 0][0 1/3]]").det(NumMode::Rational, 50), Some(parse("1/6", NumMode::Rational)));
+        assert_eq!(matrix("[[1 2]]").det(NumMode::Float, 50), None);
+    }
+
+    #[test]
+    fn inverts_matrices() {
+        let inverse = matrix("[[1 2][3 4]]").inverse(NumMode::Rational, 50);
+        assert_eq!(inverse, Some(matrix("[[-2 1][3/2 -1/2]]")));
+        let inverse = matrix("[[0 1][1 0]]").inverse(NumMode::Float, 50);
+        assert_eq!(inverse, Some(matrix("[[0 1][1 0]]")));
+        assert_eq!(matrix("[[1 2][2 4]]").inverse(NumMode::Float, 50), None);
+        assert_eq!(matrix("[[1 2 3]]").inverse(NumMode::Float, 50), None);
+    }
+
+    #[test]
+    fn solves_linear_systems() {
+        let a = parse("[[2 1][1 3]]", NumMode::Float);
+        let solve = |b: &str| super::solve(&a, &parse(b, NumMode::Float), NumMode::Float, 50);
+        assert_eq!(solve("[3 5]"), Ok(parse("[0.8 1.4]", NumMode::Float)));
+        assert_eq!(solve("[[3][4]]"), Ok(parse("[[1][1]]", NumMode::Float)));
+        assert_eq!(
+            solve("[1 2 3]"),
+            Err(CalcError::DimensionMismatch { op: "solve", left: vec![2, 2], right: vec![3] })
+        );
+        let singular = parse("[[1 2][2 4]]", NumMode::Float);
+        assert_eq!(
+            super::solve(&singular, &parse("[1 2]", NumMode::Float), NumMode::Float, 50),
+            Err(CalcError::DomainError { op: "solve", value: singular })
+        );
+    }
+
+    #[test]
+    fn rejects_values_that_arent_square_matrices() {
+        let row = parse("[[1 2]]", NumMode::Float);
+        assert_eq!(det(&row, NumMode::Float, 50), Err(CalcError::DomainError { op: "det", value: row.clone() }));
+        let two = Value::from(2);
+        assert_eq!(inverse(&two, NumMode::Float, 50), Err(CalcError::DomainError { op: "inv", value: two.clone() }));
+        assert_eq!(trace(&row), Err(CalcError::DomainError { op: "trace", value: row.clone() }));
+        assert_eq!(transpose(&row), Ok(parse("[[1][2]]", NumMode::Float)));
+        assert!(transpose(&parse("[1 2]", NumMode::Float)).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use angle::AngleMode;
+use format::{DisplayFormat, Radix};
+use stats::Statistic;
+use unit::Unit;
+use value::{NumMode, Value};
+
+/// Every available operation in the calculator
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackOp {
+    // binary operations
+    Add, // addition
+    Sub, // subtraction
+    Mul, // multiplication
+    Div, // division
+    Pow, // power
+    // unary operations
+    Sqrt, // square root
+    Neg,  // negation
+    Abs,  // absolute
+    Ln, Log, Lg, // log-e, log-10, and log-2
+    Sin, Asin,   // sin and its inverse
+    Cos, Acos,   // cos and its inverse
+    Tan, Atan,   // tan and its inverse
+    ToDeg, // converts a (radian) number to degrees
+    ToRad, // converts a (degree) number to radians
+    ToFrac,  // converts a number to an exact fraction
+    ToFloat, // converts a number to a floating point number
+    // integer operations
+    IntDiv,  // integer division, rounding down
+    Mod,     // the remainder of integer division
+    Gcd,     // greatest common divisor
+    Lcm,     // least common multiple
+    Fact,    // factorial
+    IsPrime, // pushes 1 if the number is a prime, 0 otherwise
+    // bitwise operations, on words of the configured size
+    And, Or, Xor, Not,
+    Shl, Shr, // shifts the second element by the topmost number of bits
+    Rol, Ror, // rotates the second element by the topmost number of bits
+    // complex operations
+    Re, Im,  // the real and imaginary parts
+    Arg,     // the angle to the real axis
+    Conj,    // the complex conjugate
+    ToPolar, // splits a complex number into its length and angle
+    ToRect,  // joins a length and an angle into a complex number
+    // list operations
+    Dot,     // the dot product of two lists
+    Cross,   // the cross product of two lists of three
+    Norm,    // the length of a list seen as a vector
+    Len,     // the number of elements in a list
+    Get,     // the element of a list at the topmost index, counting from 0
+    Concat,  // joins two lists together
+    Explode, // replaces a list with its elements, followed by how many there are
+    Collect, // gathers the n topmost elements into a list
+    // matrix operations
+    Det,       // the determinant of a square matrix
+    Inv,       // the inverse of a square matrix
+    Transpose, // swaps the rows of a matrix with its columns
+    Trace,     // the sum of the diagonal of a square matrix
+    Solve,     // finds x in Ax = b, with b on top and the matrix A below it
+    // units
+    Convert(Unit), // converts the topmost number to a unit measuring the same kind of thing
+    // stack operations
+    Sum,       // Sums the entire stack
+    Prod,      // Multiplies the entire stack
+    Pop,       // pops an item off the stack
+    Clear,     // clears the stack
+    Swap,      // swaps the two topmost elements
+    Rotate,    // pushes the front to the back
+    Duplicate, // duplicates the topmost element
+    Over,      // copies the second element to the top
+    Nip,       // removes the second element
+    Tuck,      // copies the topmost element below the second
+    Rot,       // moves the third element to the top
+    MinusRot,  // moves the topmost element below the third
+    TwoDup,    // duplicates the two topmost elements
+    TwoDrop,   // removes the two topmost elements
+    TwoSwap,   // swaps the two topmost pairs of elements
+    Pick,      // copies the nth element below the top to the top
+    Roll,      // moves the nth element below the top to the top
+    Depth,     // pushes the number of elements on the stack
+    DupN,      // duplicates the n topmost elements
+    // statistics
+    Stat(Statistic),    // summarises the entire stack
+    StatTop(Statistic), // summarises the n topmost elements
+    LinReg,  // fits a line through the stack, read as alternating x and y values
+    Predict, // evaluates the fitted line at the topmost element
+    // variables
+    Store(String),  // pops the topmost element into a variable
+    Recall(String), // pushes the value of a variable
+    // settings
+    SetMode(NumMode),         // changes how numbers are entered
+    SetPrecision(u64),        // changes the significant digits kept when dividing decimals
+    SetWordSize(u32),         // changes the number of bits bitwise operations work on
+    SetSigned(bool),          // changes whether the results of bitwise operations are signed
+    SetRadix(Radix),          // changes the base integers are displayed in
+    SetAngle(AngleMode),      // changes the unit trigonometric operations measure angles in
+    SetFormat(DisplayFormat), // changes how numbers are displayed
+    SetLevels(Option<usize>), // changes how many levels of the stack are displayed
+    // history
+    Undo, // reverts the last operation
+    Redo, // reapplies the last reverted operation
+    // other
+    Num(Value), // a number
+}
@@ -0,0 +1,398 @@
+use std::error::Error;
+use std::fmt;
+
+use angle::AngleMode;
+use constant::Constant;
+use format::{DisplayFormat, Radix};
+use matrix::{Matrix, MAX_IDENTITY};
+use stats::Statistic;
+use unit::Unit;
+use op::StackOp;
+use value::{NumMode, Value};
+
+/// Words that take the token following them as part of the same command,
+/// such as "to deg" and "sto x"
+const PREFIX_WORDS: &[&str] = &[
+    "to", "sto", "rcl", "mode", "precision", "word", "fix", "sci", "eng", "show", "identity", "convert",
+];
+
+/// Raised when a token doesn't correspond to any known operation or number
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub token: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Couldn't parse {}", self.token)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Splits a line into whitespace-separated tokens.
+/// Prefix words are joined with the token that follows them,
+/// so "2 to deg" becomes ["2", "to deg"] rather than ["2", "to", "deg"],
+/// and lists are kept whole, so "[1 2 3]" is a single token.
+pub fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut words = input.split_whitespace().peekable();
+
+    while let Some(word) = words.next() {
+        if word.starts_with('[') {
+            let mut list = word.to_string();
+            while brackets_open(&list) {
+                match words.next() {
+                    Some(next) => {
+                        list.push(' ');
+                        list.push_str(next);
+                    }
+                    None => break,
+                }
+            }
+            tokens.push(list);
+            continue;
+        }
+
+        match words.peek() {
+            Some(next) if takes_argument(word, next) => tokens.push(format!("{} {}", word, words.next().unwrap())),
+            _ => tokens.push(word.to_string()),
+        }
+    }
+
+    tokens
+}
+
+/// Whether a list has more opening brackets than closing ones so far
+fn brackets_open(list: &str) -> bool {
+    list.matches('[').count() > list.matches(']').count()
+}
+
+/// Whether a prefix word takes the given token as its argument.
+/// "mode" on its own is also a statistic, so it only does when a number mode follows.
+fn takes_argument(word: &str, next: &str) -> bool {
+    match word {
+        "mode" => matches!(next, "float" | "decimal" | "rational" | "exact"),
+        word => PREFIX_WORDS.contains(&word),
+    }
+}
+
+/// Parses a string and returns a stack-operator,
+/// or None if the string isn't a known operation or a number.
+/// Numbers are represented according to the mode.
+pub fn parse_string(input: &str, mode: NumMode) -> Option<StackOp> {
+    use op::StackOp::*;
+
+    let op = match input.trim() {
+        // binary operations
+        "+" | "add" => Add,
+        "-" | "sub" | "subtract" => Sub,
+        "*" | "mul" | "multiply" => Mul,
+        "/" | "divide" => Div,
+        "^" | "pow" | "power" => Pow,
+        // unary operations
+        "abs" | "absolute" => Abs,
+        "sqrt" | "root" => Sqrt,
+        "neg" | "negate" | "~" => Neg,
+        "ln" | "loge" => Ln,
+        "log" | "log10" => Log,
+        "lg" | "log2" => Lg,
+        "sin" => Sin,
+        "asin" | "sin^-1" => Asin,
+        "cos" => Cos,
+        "acos" | "cos^-1" => Acos,
+        "tan" => Tan,
+        "atan" | "tan^-1" => Atan,
+        "to deg" => ToDeg,
+        "to rad" => ToRad,
+        "->frac" | "to frac" => ToFrac,
+        "->float" | "to float" => ToFloat,
+        // integer operations
+        "div" | "idiv" => IntDiv,
+        "mod" | "%" => Mod,
+        "gcd" => Gcd,
+        "lcm" => Lcm,
+        "fact" | "!" => Fact,
+        "isprime" | "prime?" => IsPrime,
+        // bitwise operations
+        "and" | "&" => And,
+        "or" | "|" => Or,
+        "xor" => Xor,
+        "not" => Not,
+        "shl" | "<<" => Shl,
+        "shr" | ">>" => Shr,
+        "rol" => Rol,
+        "ror" => Ror,
+        // complex operations
+        "re" | "real" => Re,
+        "im" | "imag" => Im,
+        "arg" => Arg,
+        "conj" => Conj,
+        "->polar" | "to polar" => ToPolar,
+        "->rect" | "to rect" => ToRect,
+        // list operations
+        "dot" => Dot,
+        "cross" => Cross,
+        "norm" => Norm,
+        "len" | "length" => Len,
+        "get" => Get,
+        "concat" => Concat,
+        "explode" => Explode,
+        "collect" => Collect,
+        // matrix operations
+        "det" => Det,
+        "inv" | "inverse" => Inv,
+        "transpose" => Transpose,
+        "trace" => Trace,
+        "solve" => Solve,
+        // stack operations
+        "linreg" => LinReg,
+        "predict" => Predict,
+        "sum" => Sum,
+        "prod" => Prod,
+        "pop" | "drop" => Pop,
+        "clear" | "cls" => Clear,
+        "swap" => Swap,
+        "rotate" => Rotate,
+        "dup" | "copy" | "clone" | "duplicate" => Duplicate,
+        "over" => Over,
+        "nip" => Nip,
+        "tuck" => Tuck,
+        "rot" => Rot,
+        "-rot" => MinusRot,
+        "2dup" => TwoDup,
+        "2drop" => TwoDrop,
+        "2swap" => TwoSwap,
+        "pick" => Pick,
+        "roll" => Roll,
+        "depth" => Depth,
+        "dupn" => DupN,
+        // settings
+        "hex" => SetRadix(Radix::Hex),
+        "dec" => SetRadix(Radix::Dec),
+        "oct" => SetRadix(Radix::Oct),
+        "bin" => SetRadix(Radix::Bin),
+        "deg" | "degrees" => SetAngle(AngleMode::Deg),
+        "rad" | "radians" => SetAngle(AngleMode::Rad),
+        "grad" | "gradians" => SetAngle(AngleMode::Grad),
+        "std" => SetFormat(DisplayFormat::Std),
+        "signed" => SetSigned(true),
+        "unsigned" => SetSigned(false),
+        // history
+        "undo" | "u" => Undo,
+        "redo" => Redo,
+        // constant or number
+        str => {
+            return Constant::find(str)
+                .map(|constant| Num(constant.to_value(mode)))
+                .or_else(|| Value::parse(str, mode).map(Num))
+                .or_else(|| parse_statistic(str))
+                .or_else(|| parse_prefixed(str))
+        }
+    };
+
+    Some(op)
+}
+
+/// Parses the statistics, which summarise the entire stack, such as "mean",
+/// or the n topmost elements when prefixed with n, such as "3 nmean"
+fn parse_statistic(input: &str) -> Option<StackOp> {
+    let statistic = |name: &str| match name {
+        "count" => Some(Statistic::Count),
+        "mean" | "avg" => Some(Statistic::Mean),
+        "median" => Some(Statistic::Median),
+        "mode" => Some(Statistic::Mode),
+        "var" => Some(Statistic::Var),
+        "pvar" => Some(Statistic::PVar),
+        "stdev" | "sdev" => Some(Statistic::Stdev),
+        "pstdev" | "psdev" => Some(Statistic::PStdev),
+        "min" => Some(Statistic::Min),
+        "max" => Some(Statistic::Max),
+        "range" => Some(Statistic::Range),
+        "percentile" | "pctl" => Some(Statistic::Percentile),
+        _ => None,
+    };
+
+    match statistic(input) {
+        Some(statistic) => Some(StackOp::Stat(statistic)),
+        None => input.strip_prefix('n').and_then(statistic).map(StackOp::StatTop),
+    }
+}
+
+/// Parses the commands that take an argument, such as "sto x"
+fn parse_prefixed(input: &str) -> Option<StackOp> {
+    use op::StackOp::*;
+
+    match input.split_once(' ')? {
+        ("sto", name) if is_name(name) => Some(Store(name.to_string())),
+        ("rcl", name) if is_name(name) => Some(Recall(name.to_string())),
+        ("mode", "float") => Some(SetMode(NumMode::Float)),
+        ("mode", "decimal") => Some(SetMode(NumMode::Decimal)),
+        ("mode", "rational") | ("mode", "exact") => Some(SetMode(NumMode::Rational)),
+        ("precision", digits) => digits.parse().ok().filter(|&n| n > 0).map(SetPrecision),
+        ("word", bits @ ("8" | "16" | "32" | "64")) => bits.parse().ok().map(SetWordSize),
+        ("fix", digits) => digits.parse().ok().map(|n| SetFormat(DisplayFormat::Fix(n))),
+        ("sci", digits) => digits.parse().ok().map(|n| SetFormat(DisplayFormat::Sci(n))),
+        ("eng", digits) => digits.parse().ok().map(|n| SetFormat(DisplayFormat::Eng(n))),
+        ("show", "all") => Some(SetLevels(None)),
+        ("show", levels) => levels.parse().ok().filter(|&n| n > 0).map(|n| SetLevels(Some(n))),
+        ("convert", unit) => Unit::parse(unit).map(Convert),
+        ("identity", n) => n
+            .parse()
+            .ok()
+            .filter(|n| (1..=MAX_IDENTITY).contains(n))
+            .map(|n| Num(Value::Matrix(Matrix::identity(n)))),
+        _ => None,
+    }
+}
+
+/// Whether a string can be used to name a word.
+/// Words are looked up before anything else, so they may hide operations,
+/// but not the tokens that never reach the lookup: ":" and ";", the prefix words, which take
+/// the following token along, and numbers, which would mean something else in another mode.
+pub fn is_word_name(input: &str) -> bool {
+    let modes = [NumMode::Float, NumMode::Decimal, NumMode::Rational];
+    let first = input.split_whitespace().next().unwrap_or("");
+    !input.is_empty()
+        && input != ":"
+        && input != ";"
+        && !PREFIX_WORDS.contains(&first)
+        && modes.iter().all(|&mode| Value::parse(input, mode).is_none())
+}
+
+/// Whether a string can be used to name a variable.
+/// On top of the rules for words, anything that already means something in one of the modes can't,
+/// as the variable couldn't be recalled by name, except for the constants, which variables hide.
+fn is_name(input: &str) -> bool {
+    let modes = [NumMode::Float, NumMode::Decimal, NumMode::Rational];
+    is_word_name(input)
+        && (Constant::find(input).is_some() || modes.iter().all(|&mode| parse_string(input, mode).is_none()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use op::StackOp::*;
+
+    fn parse(input: &str) -> Option<StackOp> {
+        parse_string(input, NumMode::Float)
+    }
+
+    #[test]
+    fn splits_a_line_into_tokens() {
+        assert_eq!(tokenize("  2 3\t+  "), vec!["2", "3", "+"]);
+        assert_eq!(tokenize(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keeps_commands_with_their_argument() {
+        assert_eq!(tokenize("90 to rad sin"), vec!["90", "to rad", "sin"]);
+        assert_eq!(tokenize("5 sto x rcl x"), vec!["5", "sto x", "rcl x"]);
+    }
+
+    #[test]
+    fn keeps_lists_and_matrices_whole() {
+        assert_eq!(tokenize("[1 2 3] [4 5] +"), vec!["[1 2 3]", "[4 5]", "+"]);
+        assert_eq!(tokenize("[[1 2] [3 4]] det"), vec!["[[1 2] [3 4]]", "det"]);
+        assert_eq!(tokenize("[1 2"), vec!["[1 2"]);
+    }
+
+    #[test]
+    fn only_joins_mode_with_a_number_mode() {
+        assert_eq!(tokenize("1 2 mode decimal"), vec!["1", "2", "mode decimal"]);
+        assert_eq!(tokenize("1 2 2 mode dup"), vec!["1", "2", "2", "mode", "dup"]);
+        assert_eq!(parse("mode"), Some(Stat(Statistic::Mode)));
+        assert_eq!(parse("mode exact"), Some(SetMode(NumMode::Rational)));
+    }
+
+    #[test]
+    fn parses_operations_and_their_aliases() {
+        assert_eq!(parse("+"), Some(Add));
+        assert_eq!(parse("dup"), Some(Duplicate));
+        assert_eq!(parse("clone"), Some(Duplicate));
+        assert_eq!(parse("-rot"), Some(MinusRot));
+        assert_eq!(parse("u"), Some(Undo));
+        assert_eq!(parse("hex"), Some(SetRadix(Radix::Hex)));
+        assert_eq!(parse("deg"), Some(SetAngle(AngleMode::Deg)));
+        assert_eq!(parse("bogus"), None);
+    }
+
+    #[test]
+    fn parses_numbers_and_constants() {
+        assert_eq!(parse("-7"), Some(Num(Value::from(-7))));
+        assert_eq!(parse("1/2"), None);
+        assert!(parse_string("1/2", NumMode::Rational).is_some());
+        assert_eq!(parse("pi"), Some(Num(Value::Float(std::f64::consts::PI))));
+        assert_eq!(parse("π"), parse("pi"));
+    }
+
+    #[test]
+    fn parses_statistics_with_and_without_a_count() {
+        assert_eq!(parse("mean"), Some(Stat(Statistic::Mean)));
+        assert_eq!(parse("avg"), Some(Stat(Statistic::Mean)));
+        assert_eq!(parse("nmean"), Some(StatTop(Statistic::Mean)));
+        assert_eq!(parse("npctl"), Some(StatTop(Statistic::Percentile)));
+        assert_eq!(parse("nbogus"), None);
+    }
+
+    #[test]
+    fn parses_the_precision() {
+        assert_eq!(parse("precision 10"), Some(SetPrecision(10)));
+        assert_eq!(parse("precision 0"), None);
+    }
+
+    #[test]
+    fn parses_the_word_size() {
+        assert_eq!(parse("word 16"), Some(SetWordSize(16)));
+        assert_eq!(parse("word 12"), None);
+    }
+
+    #[test]
+    fn parses_display_formats() {
+        assert_eq!(parse("fix 3"), Some(SetFormat(DisplayFormat::Fix(3))));
+        assert_eq!(parse("eng x"), None);
+    }
+
+    #[test]
+    fn parses_how_many_levels_to_show() {
+        assert_eq!(parse("show all"), Some(SetLevels(None)));
+        assert_eq!(parse("show 0"), None);
+    }
+
+    #[test]
+    fn parses_identity_matrices() {
+        assert_eq!(parse("identity 2"), Some(Num(Value::Matrix(Matrix::identity(2)))));
+        assert!(parse("identity 100").is_some());
+        assert_eq!(parse("identity 101"), None);
+        assert_eq!(parse("identity 0"), None);
+    }
+
+    #[test]
+    fn parses_units_to_convert_to() {
+        assert_eq!(parse("convert km"), Some(Convert(Unit::parse("km").unwrap())));
+        assert_eq!(parse("convert parsec"), None);
+    }
+
+    #[test]
+    fn only_stores_names_that_can_be_recalled() {
+        assert_eq!(parse("sto x"), Some(Store("x".to_string())));
+        assert_eq!(parse("rcl total"), Some(Recall("total".to_string())));
+        // variables hide constants
+        assert_eq!(parse("sto c"), Some(Store("c".to_string())));
+        assert_eq!(parse("sto pi"), Some(Store("pi".to_string())));
+
+        for taken in &["12", "1e3", "1/2", "2.5", "i", "3+4i", "[1]", "max", "nmax", "dup", "sto", ":", "mode"] {
+            assert_eq!(parse(&format!("sto {}", taken)), None, "sto {}", taken);
+        }
+    }
+
+    #[test]
+    fn only_names_words_that_can_be_called() {
+        // words hide operations
+        assert!(is_word_name("sq"));
+        assert!(is_word_name("dup"));
+        for taken in &["12", "1/2", "3+4i", "[1]", ":", ";", "sto", "sto x", "mode"] {
+            assert!(!is_word_name(taken), "{}", taken);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+
+use error::CalcError;
+use unit;
+use value::{NumMode, Value};
+
+/// A statistic that summarises a sample of numbers into a single one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Statistic {
+    Count,
+    Mean,
+    Median,
+    Mode,   // the most common number, or the smallest of them if there's a tie
+    Var,    // the sample variance
+    PVar,   // the population variance
+    Stdev,  // the sample standard deviation
+    PStdev, // the population standard deviation
+    Min,
+    Max,
+    Range,      // the difference between the largest and smallest numbers
+    Percentile, // interpolates between the closest ranks, taking the percentage from the stack
+}
+
+impl Statistic {
+    /// The name used when reporting errors
+    pub fn name(self) -> &'static str {
+        match self {
+            Statistic::Count => "count",
+            Statistic::Mean => "mean",
+            Statistic::Median => "median",
+            Statistic::Mode => "mode",
+            Statistic::Var => "var",
+            Statistic::PVar => "pvar",
+            Statistic::Stdev => "stdev",
+            Statistic::PStdev => "pstdev",
+            Statistic::Min => "min",
+            Statistic::Max => "max",
+            Statistic::Range => "range",
+            Statistic::Percentile => "percentile",
+        }
+    }
+
+    /// The fewest numbers the statistic can be computed for
+    fn minimum(self) -> usize {
+        match self {
+            Statistic::Count => 0,
+            Statistic::Var | Statistic::Stdev => 2,
+            _ => 1,
+        }
+    }
+
+    /// Computes the statistic over a sample.
+    /// Percentiles also need the percentage, between 0 and 100.
+    /// Exact numbers stay exact wherever possible, with divisions following the mode.
+    pub fn compute(
+        self,
+        sample: Vec<Value>,
+        percent: Option<Value>,
+        mode: NumMode,
+        precision: u64,
+    ) -> Result<Value, CalcError> {
+        if sample.len() < self.minimum() {
+            return Err(CalcError::StackUnderflow { needed: self.minimum(), available: sample.len() });
+        }
+        let n = sample.len();
+
+        let result = match self {
+            Statistic::Count => Value::Int(BigInt::from(n)),
+            Statistic::Mean => mean(sample, mode, precision),
+            Statistic::Median => {
+                let sorted = sort(self, sample)?;
+                if n % 2 == 1 {
+                    sorted[n / 2].clone()
+                } else {
+                    mean(sorted[n / 2 - 1..=n / 2].to_vec(), mode, precision)
+                }
+            }
+            Statistic::Mode => most_common(sort(self, sample)?),
+            Statistic::Var => variance(sample, n - 1, mode, precision),
+            Statistic::PVar => variance(sample, n, mode, precision),
+            Statistic::Stdev => variance(sample, n - 1, mode, precision).pow(Value::Float(0.5), mode, precision),
+            Statistic::PStdev => variance(sample, n, mode, precision).pow(Value::Float(0.5), mode, precision),
+            Statistic::Min => sort(self, sample)?.swap_remove(0),
+            Statistic::Max => sort(self, sample)?.pop().unwrap(),
+            Statistic::Range => {
+                let mut sorted = sort(self, sample)?;
+                let max = sorted.pop().unwrap();
+                (max - sorted.swap_remove(0)).round_to(precision)
+            }
+            Statistic::Percentile => {
+                let percent = percent.expect("percentiles are computed with a percentage");
+                percentile(sort(self, sample)?, percent, precision)?
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+/// Sorts a sample, which must consist of real numbers
+fn sort(statistic: Statistic, mut sample: Vec<Value>) -> Result<Vec<Value>, CalcError> {
+    if let Some(unordered) = sample.iter().find(|x| x.compare(x).is_none()) {
+        return Err(CalcError::DomainError { op: statistic.name(), value: unordered.clone() });
+    }
+    sample.sort_by(|a, b| a.compare(b).unwrap_or(Ordering::Equal));
+    Ok(sample)
+}
+
+/// Adds the sample together, then divides it by its size.
+/// The sum starts from the first number rather than 0, and is kept in its unit.
+fn mean(sample: Vec<Value>, mode: NumMode, precision: u64) -> Value {
+    let n = sample.len() as i64;
+    let mut sample = sample.into_iter();
+    let first = sample.next().expect("the mean is taken of at least one number");
+    let sum = sample.fold(first, |acc, x| {
+        let x = unit::align(&acc, x, mode, precision);
+        (acc + x).round_to(precision)
+    });
+    sum.div(Value::from(n), mode, precision)
+}
+
+/// The sum of the squared distances to the mean, divided by `degrees` of freedom
+fn variance(sample: Vec<Value>, degrees: usize, mode: NumMode, precision: u64) -> Value {
+    let mean = mean(sample.clone(), mode, precision);
+    let mut squares = sample.into_iter().map(|x| {
+        let distance = (unit::align(&mean, x, mode, precision) - mean.clone()).round_to(precision);
+        (distance.clone() * distance).round_to(precision)
+    });
+    let first = squares.next().expect("the variance is taken of at least one number");
+    let sum = squares.fold(first, |acc, square| (acc + square).round_to(precision));
+    sum.div(Value::from(degrees as i64), mode, precision)
+}
+
+/// Finds the number that occurs the most in a sorted sample, preferring the smallest on a tie
+fn most_common(sorted: Vec<Value>) -> Value {
+    let mut best = (0, 0); // the start and length of the longest run of equal numbers
+    let mut start = 0;
+
+    for i in 1..=sorted.len() {
+        let run_ends = i == sorted.len() || sorted[i].compare(&sorted[start]) != Some(Ordering::Equal);
+        if run_ends {
+            if i - start > best.1 {
+                best = (start, i - start);
+            }
+            start = i;
+        }
+    }
+
+    sorted[best.0].clone()
+}
+
+/// Finds the given percentile of a sorted sample, interpolating linearly between
+/// the two closest ranks, so the 0th is the smallest number and the 100th the largest
+fn percentile(sorted: Vec<Value>, percent: Value, precision: u64) -> Result<Value, CalcError> {
+    let p = percent.to_f64();
+    if !(0.0..=100.0).contains(&p) {
+        return Err(CalcError::DomainError { op: "percentile", value: percent });
+    }
+
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let fraction = rank - rank.floor();
+    if fraction == 0.0 {
+        return Ok(sorted[below].clone());
+    }
+
+    let (low, high) = (sorted[below].clone(), sorted[below + 1].clone());
+    Ok((low.clone() + (high - low) * Value::Float(fraction)).round_to(precision))
+}
+
+/// The straight line that best fits a set of points, by least squares
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearFit {
+    pub slope: Value,
+    pub intercept: Value,
+}
+
+impl LinearFit {
+    /// Fits a line through the points, returning it along with its coefficient of determination, r².
+    /// The sums are folded exactly wherever possible, so exact points give an exact fit,
+    /// and are taken around the means, so floats don't lose more digits than they need to.
+    /// At least two points with different x are needed for there to be a single best line.
+    pub fn new(points: Vec<(Value, Value)>, mode: NumMode, precision: u64) -> Result<(LinearFit, Value), CalcError> {
+        if points.len() < 2 {
+            return Err(CalcError::StackUnderflow { needed: 4, available: points.len() * 2 });
+        }
+        let first_x = points[0].0.clone();
+        let (xs, ys): (Vec<Value>, Vec<Value>) = points.iter().cloned().unzip();
+        let (mean_x, mean_y) = (mean(xs, mode, precision), mean(ys, mode, precision));
+
+        // the sums of the squared and multiplied distances to the means
+        let zero = || Value::from(0);
+        let add = |sum: Value, term: Value| (sum + term).round_to(precision);
+        let (sxx, sxy, syy) = points.into_iter().fold((zero(), zero(), zero()), |(sxx, sxy, syy), (x, y)| {
+            let dx = (x - mean_x.clone()).round_to(precision);
+            let dy = (y - mean_y.clone()).round_to(precision);
+            (add(sxx, dx.clone() * dx.clone()), add(sxy, dx * dy.clone()), add(syy, dy.clone() * dy))
+        });
+
+        if sxx.is_zero() {
+            return Err(CalcError::DomainError { op: "linreg", value: first_x });
+        }
+
+        let slope = sxy.clone().div(sxx.clone(), mode, precision);
+        let intercept = (mean_y - slope.clone() * mean_x).round_to(precision);
+        // points that all share the same y lie on the fitted line exactly
+        let r_squared = if syy.is_zero() {
+            Value::from(1)
+        } else {
+            (sxy.clone() * sxy).div(sxx * syy, mode, precision)
+        };
+
+        Ok((LinearFit { slope, intercept }, r_squared))
+    }
+
+    /// Evaluates the line at x
+    pub fn predict(&self, x: Value, precision: u64) -> Value {
+        (self.slope.clone() * x + self.intercept.clone()).round_to(precision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_rational::BigRational;
+
+    fn ints(xs: &[i64]) -> Vec<Value> {
+        xs.iter().map(|&x| Value::from(x)).collect()
+    }
+
+    fn compute(statistic: Statistic, xs: &[i64], mode: NumMode) -> Result<Value, CalcError> {
+        statistic.compute(ints(xs), None, mode, 50)
+    }
+
+    fn pctl(xs: &[i64], percent: f64) -> Result<Value, CalcError> {
+        Statistic::Percentile.compute(ints(xs), Some(Value::Float(percent)), NumMode::Float, 50)
+    }
+
+    fn points(pairs: &[(i64, i64)]) -> Vec<(Value, Value)> {
+        pairs.iter().map(|&(x, y)| (Value::from(x), Value::from(y))).collect()
+    }
+
+    #[test]
+    fn summarises_a_sample() {
+        let xs = [4, 1, 3, 1, 6];
+        assert_eq!(compute(Statistic::Count, &xs, NumMode::Float), Ok(Value::from(5)));
+        assert_eq!(compute(Statistic::Mean, &xs, NumMode::Float), Ok(Value::from(3)));
+        assert_eq!(compute(Statistic::Median, &xs, NumMode::Float), Ok(Value::from(3)));
+        assert_eq!(compute(Statistic::Median, &[1, 2, 3, 4], NumMode::Float), Ok(Value::Float(2.5)));
+        assert_eq!(compute(Statistic::Mode, &xs, NumMode::Float), Ok(Value::from(1)));
+        assert_eq!(compute(Statistic::Mode, &[3, 2, 3, 2], NumMode::Float), Ok(Value::from(2)));
+        assert_eq!(compute(Statistic::Min, &xs, NumMode::Float), Ok(Value::from(1)));
+        assert_eq!(compute(Statistic::Max, &xs, NumMode::Float), Ok(Value::from(6)));
+        assert_eq!(compute(Statistic::Range, &xs, NumMode::Float), Ok(Value::from(5)));
+    }
+
+    #[test]
+    fn keeps_exact_samples_exact() {
+        let third = BigRational::new(BigInt::from(7), BigInt::from(3));
+        assert_eq!(compute(Statistic::Mean, &[1, 2, 4], NumMode::Rational), Ok(Value::Rational(third)));
+        assert_eq!(compute(Statistic::Var, &[2, 4, 4, 4, 5, 5, 7, 9], NumMode::Float), Ok(Value::Float(32.0 / 7.0)));
+        assert_eq!(compute(Statistic::PVar, &[2, 4, 4, 4, 5, 5, 7, 9], NumMode::Float), Ok(Value::from(4)));
+        assert_eq!(compute(Statistic::PStdev, &[2, 4, 4, 4, 5, 5, 7, 9], NumMode::Float), Ok(Value::Float(2.0)));
+    }
+
+    #[test]
+    fn keeps_the_unit_of_the_sample() {
+        let metres = |xs: &[&str]| -> Vec<Value> {
+            xs.iter().map(|x| Value::parse(&format!("{}_m", x), NumMode::Decimal).unwrap()).collect()
+        };
+        let compute = |statistic: Statistic| statistic.compute(metres(&["1", "2", "3"]), None, NumMode::Decimal, 50);
+        assert_eq!(compute(Statistic::Mean), Ok(Value::parse("2_m", NumMode::Decimal).unwrap()));
+        assert_eq!(compute(Statistic::Var), Ok(Value::parse("1_m^2", NumMode::Decimal).unwrap()));
+        assert_eq!(compute(Statistic::Stdev), Ok(Value::parse("1.0_m", NumMode::Float).unwrap()));
+    }
+
+    #[test]
+    fn interpolates_percentiles() {
+        let xs = [15, 20, 35, 40, 50];
+        assert_eq!(pctl(&xs, 0.0), Ok(Value::from(15)));
+        assert_eq!(pctl(&xs, 50.0), Ok(Value::from(35)));
+        assert_eq!(pctl(&xs, 100.0), Ok(Value::from(50)));
+        assert_eq!(pctl(&xs, 40.0), Ok(Value::Float(29.0)));
+        assert_eq!(pctl(&[7], 30.0), Ok(Value::from(7)));
+        assert!(matches!(pctl(&xs, 101.0), Err(CalcError::DomainError { op: "percentile", .. })));
+        assert!(matches!(pctl(&xs, -1.0), Err(CalcError::DomainError { op: "percentile", .. })));
+    }
+
+    #[test]
+    fn needs_enough_real_numbers() {
+        assert_eq!(
+            compute(Statistic::Stdev, &[1], NumMode::Float),
+            Err(CalcError::StackUnderflow { needed: 2, available: 1 })
+        );
+        assert_eq!(compute(Statistic::Count, &[], NumMode::Float), Ok(Value::from(0)));
+        let complex = vec![Value::from(1), Value::parse("2i", NumMode::Float).unwrap()];
+        assert!(matches!(
+            Statistic::Median.compute(complex, None, NumMode::Float, 50),
+            Err(CalcError::DomainError { op: "median", .. })
+        ));
+    }
+
+    #[test]
+    fn fits_a_line_through_points() {
+        let (fit, r_squared) = LinearFit::new(points(&[(1, 3), (2, 5), (3, 7)]), NumMode::Float, 50).unwrap();
+        assert_eq!(fit.slope, Value::from(2));
+        assert_eq!(fit.intercept, Value::from(1));
+        assert_eq!(r_squared, Value::from(1));
+        assert_eq!(fit.predict(Value::from(10), 50), Value::from(21));
+
+        let (fit, r_squared) = LinearFit::new(points(&[(0, 0), (1, 1), (2, 0)]), NumMode::Rational, 50).unwrap();
+        assert_eq!(fit.slope, Value::from(0));
+        assert_eq!(fit.intercept, Value::Rational(BigRational::new(BigInt::from(1), BigInt::from(3))));
+        assert_eq!(r_squared, Value::from(0));
+    }
+
+    #[test]
+    fn needs_two_points_with_different_x() {
+        assert_eq!(
+            LinearFit::new(points(&[(1, 2)]), NumMode::Float, 50),
+            Err(CalcError::StackUnderflow { needed: 4, available: 2 })
+        );
+        assert!(matches!(
+            LinearFit::new(points(&[(1, 2), (1, 3)]), NumMode::Float, 50),
+            Err(CalcError::DomainError { op: "linreg", .. })
+        ));
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt;
+use std::ops::Mul;
+
+use error::CalcError;
+use value::{NumMode, Value};
+
+/// The powers of the base quantities a unit measures:
+/// length, mass, time, current, temperature, amount of substance and luminous intensity
+type Dimension = [i32; 7];
+
+const NONE: Dimension = [0, 0, 0, 0, 0, 0, 0];
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0];
+const CURRENT: Dimension = [0, 0, 0, 1, 0, 0, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 0, 1, 0, 0];
+const AMOUNT: Dimension = [0, 0, 0, 0, 0, 1, 0];
+const LUMINOSITY: Dimension = [0, 0, 0, 0, 0, 0, 1];
+const AREA: Dimension = [2, 0, 0, 0, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0];
+const FREQUENCY: Dimension = [0, 0, -1, 0, 0, 0, 0];
+const FORCE: Dimension = [1, 1, -2, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0];
+const CHARGE: Dimension = [0, 0, 1, 1, 0, 0, 0];
+const VOLTAGE: Dimension = [2, 1, -3, -1, 0, 0, 0];
+const RESISTANCE: Dimension = [2, 1, -3, -2, 0, 0, 0];
+
+/// The largest power a part of a unit can have either way,
+/// which keeps the powers of the base quantities well within an i32
+const MAX_POWER: i32 = 1000;
+
+/// Whether a part of a unit can have the given power
+fn power_fits(power: i32) -> bool {
+    (-MAX_POWER..=MAX_POWER).contains(&power)
+}
+
+/// A unit that can be written on its own, such as "ft"
+#[derive(Debug, PartialEq)]
+struct Named {
+    symbol: &'static str,
+    factor: &'static str, // how many of the SI unit of its dimension it is, exactly where possible
+    dimension: Dimension,
+}
+
+/// Every unit that can be written on its own
+const UNITS: &[Named] = &[
+    // length
+    Named { symbol: "m", factor: "1", dimension: LENGTH },
+    Named { symbol: "km", factor: "1000", dimension: LENGTH },
+    Named { symbol: "cm", factor: "0.01", dimension: LENGTH },
+    Named { symbol: "mm", factor: "0.001", dimension: LENGTH },
+    Named { symbol: "um", factor: "0.000001", dimension: LENGTH },
+    Named { symbol: "nm", factor: "0.000000001", dimension: LENGTH },
+    Named { symbol: "in", factor: "0.0254", dimension: LENGTH },
+    Named { symbol: "ft", factor: "0.3048", dimension: LENGTH },
+    Named { symbol: "yd", factor: "0.9144", dimension: LENGTH },
+    Named { symbol: "mi", factor: "1609.344", dimension: LENGTH },
+    Named { symbol: "nmi", factor: "1852", dimension: LENGTH },
+    // mass
+    Named { symbol: "kg", factor: "1", dimension: MASS },
+    Named { symbol: "g", factor: "0.001", dimension: MASS },
+    Named { symbol: "mg", factor: "0.000001", dimension: MASS },
+    Named { symbol: "t", factor: "1000", dimension: MASS },
+    Named { symbol: "lb", factor: "0.45359237", dimension: MASS },
+    Named { symbol: "oz", factor: "0.028349523125", dimension: MASS },
+    Named { symbol: "st", factor: "6.35029318", dimension: MASS },
+    // time
+    Named { symbol: "s", factor: "1", dimension: TIME },
+    Named { symbol: "ms", factor: "0.001", dimension: TIME },
+    Named { symbol: "min", factor: "60", dimension: TIME },
+    Named { symbol: "h", factor: "3600", dimension: TIME },
+    Named { symbol: "d", factor: "86400", dimension: TIME },
+    Named { symbol: "wk", factor: "604800", dimension: TIME },
+    // the other base units
+    Named { symbol: "A", factor: "1", dimension: CURRENT },
+    Named { symbol: "K", factor: "1", dimension: TEMPERATURE },
+    Named { symbol: "mol", factor: "1", dimension: AMOUNT },
+    Named { symbol: "cd", factor: "1", dimension: LUMINOSITY },
+    // area
+    Named { symbol: "ha", factor: "10000", dimension: AREA },
+    Named { symbol: "acre", factor: "4046.8564224", dimension: AREA },
+    // volume
+    Named { symbol: "L", factor: "0.001", dimension: VOLUME },
+    Named { symbol: "mL", factor: "0.000001", dimension: VOLUME },
+    Named { symbol: "gal", factor: "0.003785411784", dimension: VOLUME },
+    Named { symbol: "qt", factor: "0.000946352946", dimension: VOLUME },
+    Named { symbol: "pt", factor: "0.000473176473", dimension: VOLUME },
+    Named { symbol: "floz", factor: "0.0000295735295625", dimension: VOLUME },
+    // frequency
+    Named { symbol: "Hz", factor: "1", dimension: FREQUENCY },
+    // force
+    Named { symbol: "N", factor: "1", dimension: FORCE },
+    Named { symbol: "kN", factor: "1000", dimension: FORCE },
+    Named { symbol: "lbf", factor: "4.4482216152605", dimension: FORCE },
+    // energy
+    Named { symbol: "J", factor: "1", dimension: ENERGY },
+    Named { symbol: "kJ", factor: "1000", dimension: ENERGY },
+    Named { symbol: "cal", factor: "4.184", dimension: ENERGY },
+    Named { symbol: "kcal", factor: "4184", dimension: ENERGY },
+    Named { symbol: "Wh", factor: "3600", dimension: ENERGY },
+    Named { symbol: "kWh", factor: "3600000", dimension: ENERGY },
+    Named { symbol: "BTU", factor: "1055.05585262", dimension: ENERGY },
+    // power
+    Named { symbol: "W", factor: "1", dimension: POWER },
+    Named { symbol: "kW", factor: "1000", dimension: POWER },
+    Named { symbol: "hp", factor: "745.69987158227022", dimension: POWER },
+    // pressure
+    Named { symbol: "Pa", factor: "1", dimension: PRESSURE },
+    Named { symbol: "kPa", factor: "1000", dimension: PRESSURE },
+    Named { symbol: "bar", factor: "100000", dimension: PRESSURE },
+    Named { symbol: "atm", factor: "101325", dimension: PRESSURE },
+    Named { symbol: "psi", factor: "6894.757293168361", dimension: PRESSURE },
+    // electricity
+    Named { symbol: "C", factor: "1", dimension: CHARGE },
+    Named { symbol: "V", factor: "1", dimension: VOLTAGE },
+    Named { symbol: "ohm", factor: "1", dimension: RESISTANCE },
+];
+
+/// A unit a number can be measured in, made up of named units raised to whole powers,
+/// such as m/s^2. Having no parts at all means the number has no unit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    parts: Vec<(&'static Named, i32)>, // in the order they were written, without zero powers
+}
+
+impl Unit {
+    /// The unit of plain numbers
+    pub fn none() -> Unit {
+        Unit { parts: Vec::new() }
+    }
+
+    /// Parses a unit such as "m", "m^2", "km/h" or "kg*m/s^2".
+    /// Each / divides by the single unit that follows it, so "J/kg/K" is J kg^-1 K^-1.
+    /// Powers beyond MAX_POWER either way aren't units.
+    /// Symbols are matched ignoring case, since input is lowercased and no two symbols differ by case alone.
+    pub fn parse(input: &str) -> Option<Unit> {
+        let mut unit = Unit::none();
+        let mut sign = 1;
+        let mut rest = input;
+
+        loop {
+            let end = rest.find(['*', '/']).unwrap_or(rest.len());
+            let (symbol, power) = match rest[..end].split_once('^') {
+                Some((symbol, power)) => (symbol, power.parse::<i32>().ok().filter(|&power| power_fits(power))?),
+                None => (&rest[..end], 1),
+            };
+            let named = UNITS.iter().find(|named| named.symbol.eq_ignore_ascii_case(symbol))?;
+            unit = unit.times(&Unit { parts: vec![(named, power)] }, sign)?;
+
+            if end == rest.len() {
+                break;
+            }
+            sign = if rest[end..].starts_with('/') { -1 } else { 1 };
+            rest = &rest[end + 1..];
+        }
+
+        Some(unit)
+    }
+
+    /// Whether this is the unit of plain numbers
+    pub fn is_none(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    /// The powers of the base quantities this unit measures
+    fn dimension(&self) -> Dimension {
+        let mut dimension = NONE;
+        for &(named, power) in &self.parts {
+            for (total, base) in dimension.iter_mut().zip(named.dimension.iter()) {
+                *total += base * power;
+            }
+        }
+        dimension
+    }
+
+    /// Whether two units measure the same kind of thing, such as m and ft
+    pub fn compatible(&self, other: &Unit) -> bool {
+        self.dimension() == other.dimension()
+    }
+
+    /// How many of the SI unit of its dimension this unit is
+    fn factor(&self, mode: NumMode, precision: u64) -> Value {
+        self.parts.iter().fold(Value::from(1), |acc, &(named, power)| {
+            let factor = Value::parse(named.factor, mode).expect("the factors of units are numbers");
+            acc.mul(factor.pow(Value::from(power as i64), mode, precision)).round_to(precision)
+        })
+    }
+
+    /// Multiplies by another unit raised to `sign`, which is 1 for multiplying and -1 for dividing.
+    /// Returns None if a part of the unit ends up with a power beyond MAX_POWER.
+    pub fn times(&self, other: &Unit, sign: i32) -> Option<Unit> {
+        let mut parts = self.parts.clone();
+        for &(named, power) in &other.parts {
+            let power = sign.checked_mul(power)?;
+            match parts.iter().position(|&(ours, _)| ours == named) {
+                Some(i) => parts[i].1 = parts[i].1.checked_add(power)?,
+                None => parts.push((named, power)),
+            }
+        }
+        if !parts.iter().all(|&(_, power)| power_fits(power)) {
+            return None;
+        }
+        parts.retain(|&(_, power)| power != 0);
+        Some(Unit { parts })
+    }
+
+    /// Raises to a power, as long as every part of the unit still has a whole power
+    /// no bigger than MAX_POWER, so m^2 can be raised to 0.5 but m can't
+    pub fn pow(&self, exponent: f64) -> Option<Unit> {
+        let mut parts = Vec::new();
+        for &(named, power) in &self.parts {
+            let power = power as f64 * exponent;
+            if power.fract() != 0.0 || power.abs() > MAX_POWER as f64 {
+                return None;
+            }
+            parts.push((named, power as i32));
+        }
+        parts.retain(|&(_, power)| power != 0);
+        Some(Unit { parts })
+    }
+}
+
+/// Converts a number to a unit measuring the same kind of thing
+pub fn convert(value: &Value, unit: &Unit, mode: NumMode, precision: u64) -> Result<Value, CalcError> {
+    let from = value.unit();
+    if !value.is_scalar() || !from.compatible(unit) {
+        return Err(CalcError::IncompatibleUnits { op: "convert", left: from, right: unit.clone() });
+    }
+
+    let (x, from) = value.clone().into_parts();
+    let ratio = from.factor(mode, precision).div(unit.factor(mode, precision), mode, precision);
+    Ok(Value::quantity(x.mul(ratio).round_to(precision), unit.clone()))
+}
+
+/// Whether two numbers can be added up, which they can if they measure the same kind of thing.
+/// Lists and matrices are left for their elements to decide.
+pub fn compatible(a: &Value, b: &Value) -> bool {
+    !a.is_scalar() || !b.is_scalar() || a.unit().compatible(&b.unit())
+}
+
+/// Rewrites `b` in the units of `a` wherever it can, so they can be combined:
+/// entirely if they measure the same kind of thing, as in 1_m and 2_ft,
+/// or part by part otherwise, as in 1_m and 2_ft/s, where b becomes m/s.
+pub fn align(a: &Value, b: Value, mode: NumMode, precision: u64) -> Value {
+    if !a.is_scalar() || !b.is_scalar() {
+        return b;
+    }
+    let ours = a.unit();
+    let theirs = b.unit();
+    if ours == theirs {
+        return b;
+    }
+    if ours.compatible(&theirs) {
+        return convert(&b, &ours, mode, precision).unwrap_or(b);
+    }
+
+    let mut unit = Unit::none();
+    for &(named, power) in &theirs.parts {
+        let like = ours.parts.iter().find(|&&(ours, _)| ours.dimension == named.dimension);
+        let named = like.map_or(named, |&(ours, _)| ours);
+        unit = match unit.times(&Unit { parts: vec![(named, power)] }, 1) {
+            Some(unit) => unit,
+            None => return b,
+        };
+    }
+    convert(&b, &unit, mode, precision).unwrap_or(b)
+}
+
+impl fmt::Display for Unit {
+    /// Writes the unit the way it's parsed, as in kg*m/s^2,
+    /// or s^-1 if nothing comes before the first division
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let part = |named: &Named, power: i32| match power {
+            1 => named.symbol.to_string(),
+            power => format!("{}^{}", named.symbol, power),
+        };
+
+        let above: Vec<String> = self.parts.iter().filter(|p| p.1 > 0).map(|p| part(p.0, p.1)).collect();
+        let below: Vec<String> = self.parts.iter().filter(|p| p.1 < 0).map(|p| part(p.0, -p.1)).collect();
+        if above.is_empty() {
+            let parts: Vec<String> = self.parts.iter().map(|p| part(p.0, p.1)).collect();
+            return write!(f, "{}", parts.join("*"));
+        }
+
+        write!(f, "{}", above.join("*"))?;
+        for part in below {
+            write!(f, "/{}", part)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(input: &str) -> Unit {
+        Unit::parse(input).unwrap()
+    }
+
+    fn decimal(input: &str) -> Value {
+        Value::parse(input, NumMode::Decimal).unwrap()
+    }
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(unit("m/s^2").to_string(), "m/s^2");
+        assert_eq!(unit("kg*m/s^2").to_string(), "kg*m/s^2");
+        assert_eq!(unit("J/kg/K").to_string(), "J/kg/K");
+        assert_eq!(unit("s^-1").to_string(), "s^-1");
+        assert_eq!(unit("m*m").to_string(), "m^2");
+        assert_eq!(unit("kwh"), unit("kWh"));
+        assert!(unit("m/m").is_none());
+        assert_eq!(Unit::parse("parsec"), None);
+        assert_eq!(Unit::parse("m^x"), None);
+        assert_eq!(Unit::parse("m/"), None);
+    }
+
+    #[test]
+    fn knows_what_units_measure() {
+        assert!(unit("km").compatible(&unit("mi")));
+        assert!(unit("J").compatible(&unit("kg*m^2/s^2")));
+        assert!(unit("W").compatible(&unit("J/s")));
+        assert!(!unit("m").compatible(&unit("s")));
+        assert!(Unit::none().compatible(&unit("m/km")));
+    }
+
+    #[test]
+    fn raises_units_to_whole_powers_only() {
+        assert_eq!(unit("m").pow(2.0), Some(unit("m^2")));
+        assert_eq!(unit("m^2/s^2").pow(0.5), Some(unit("m/s")));
+        assert_eq!(unit("m").pow(0.5), None);
+        assert_eq!(unit("m").pow(0.0), Some(Unit::none()));
+        assert_eq!(unit("m^600").pow(2.0), None);
+    }
+
+    #[test]
+    fn bounds_the_powers_of_units() {
+        assert_eq!(unit("m^1000").to_string(), "m^1000");
+        assert_eq!(unit("s^-1000").to_string(), "s^-1000");
+        assert_eq!(Unit::parse("m^1001"), None);
+        assert_eq!(Unit::parse("m^2147483647"), None);
+        assert_eq!(Unit::parse("m^-2147483648"), None);
+        assert_eq!(Unit::parse("m^1000*m"), None);
+        assert_eq!(unit("m^1000").times(&unit("m"), 1), None);
+        assert_eq!(unit("m^1000").times(&unit("m^-1"), -1), None);
+        assert_eq!(unit("m^1000").times(&unit("m^1000"), -1), Some(Unit::none()));
+    }
+
+    #[test]
+    fn converts_between_compatible_units() {
+        let convert = |x: &str, to: &str| convert(&decimal(x), &unit(to), NumMode::Decimal, 50).map(|x| x.to_string());
+        assert_eq!(convert("1_km", "m"), Ok("1000_m".to_string()));
+        assert_eq!(convert("1_ft", "in"), Ok("12_in".to_string()));
+        assert_eq!(convert("90_km/h", "m/s"), Ok("25_m/s".to_string()));
+        assert_eq!(convert("1_kwh", "J"), Ok("3600000_J".to_string()));
+        let incompatible = |left: &str, right| CalcError::IncompatibleUnits { op: "convert", left: unit(left), right };
+        assert_eq!(convert("1_km", "m/m"), Err(incompatible("km", Unit::none())));
+        assert_eq!(convert("1_m", "s"), Err(incompatible("m", unit("s"))));
+    }
+
+    #[test]
+    fn aligns_numbers_to_the_units_of_another() {
+        let align = |a: &str, b: &str| align(&decimal(a), decimal(b), NumMode::Decimal, 50).to_string();
+        assert_eq!(align("1_m", "2_km"), "2000_m");
+        assert_eq!(align("1_m", "3_ft/s"), "0.9144_m/s");
+        assert_eq!(align("1_m", "5_s"), "5_s");
+        assert_eq!(align("1", "5_s"), "5_s");
+        assert_eq!(align("1_m", "[1 2]"), "[1 2]");
+        assert!(compatible(&decimal("1_m"), &decimal("1_ft")));
+        assert!(!compatible(&decimal("1_m"), &decimal("1")));
+        assert!(compatible(&decimal("[1 2]"), &decimal("1_m")));
+    }
+}
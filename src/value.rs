@@ -0,0 +1,1064 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::mem;
+use std::ops::{Add, Sub, Mul, Neg};
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, Pow, Signed, ToPrimitive, Zero};
+
+use integer;
+use matrix::Matrix;
+use unit::Unit;
+
+/// How the numbers typed in by the user are represented
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumMode {
+    Float,   // 64-bit floating point, fast but inexact
+    Decimal, // arbitrary precision decimals, exact for + - * / up to the precision
+    Rational, // fractions of arbitrary size, exact for + - * / and whole powers
+}
+
+/// The number of significant digits kept when dividing decimals, unless told otherwise
+pub const DEFAULT_PRECISION: u64 = 50;
+
+/// The most significant digits that can be kept, as every division works out that many
+pub const MAX_PRECISION: u64 = 1000;
+
+/// The furthest a decimal typed in, or turned into a fraction, can have its point from its digits,
+/// as in 1e10000. Exact numbers carry every digit up to the point, so anything much beyond
+/// the precision would only make working with them slow.
+const MAX_EXPONENT: i64 = 10 * MAX_PRECISION as i64;
+
+/// Extra digits kept while raising decimals to a power, so the rounding along the way
+/// doesn't show in the result
+const GUARD_DIGITS: u64 = 10;
+
+/// A single element on the stack
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(BigInt),
+    Float(f64),
+    Decimal(BigDecimal),
+    Rational(BigRational),
+    Complex(Complex64),
+    List(Vec<Value>),           // a list of numbers, such as [1 2 3]
+    Matrix(Matrix),             // a matrix of numbers, such as [[1 2][3 4]]
+    Quantity(Box<Value>, Unit), // a number measured in a unit, such as 300_m
+}
+
+/// Both operands of a binary operation, converted to a common representation.
+/// Exact operands stay exact, with integers becoming decimals or fractions when mixed with them,
+/// and decimals becoming fractions when mixed with them,
+/// but as soon as one of the operands is a float, both are,
+/// and as soon as one of them is complex, both are.
+enum Pair {
+    Ints(BigInt, BigInt),
+    Floats(f64, f64),
+    Decimals(BigDecimal, BigDecimal),
+    Rationals(BigRational, BigRational),
+    Complexes(Complex64, Complex64),
+}
+
+impl Pair {
+    fn new(a: Value, b: Value) -> Self {
+        use self::Value::*;
+
+        match (a, b) {
+            (ref a, ref b) if a.is_complex() || b.is_complex() => Pair::Complexes(a.to_complex(), b.to_complex()),
+            (Int(a), Int(b)) => Pair::Ints(a, b),
+            (Int(a), Decimal(b)) => Pair::Decimals(BigDecimal::from(a), b),
+            (Decimal(a), Int(b)) => Pair::Decimals(a, BigDecimal::from(b)),
+            (Int(a), Rational(b)) => Pair::Rationals(BigRational::from_integer(a), b),
+            (Rational(a), Int(b)) => Pair::Rationals(a, BigRational::from_integer(b)),
+            (Decimal(a), Decimal(b)) => Pair::Decimals(a, b),
+            (Rational(a), Rational(b)) => Pair::Rationals(a, b),
+            (Rational(a), Decimal(b)) => match decimal_to_rational(&b) {
+                Some(b) => Pair::Rationals(a, b),
+                None => Pair::Floats(Value::Rational(a).to_f64(), Value::Decimal(b).to_f64()),
+            },
+            (Decimal(a), Rational(b)) => match decimal_to_rational(&a) {
+                Some(a) => Pair::Rationals(a, b),
+                None => Pair::Floats(Value::Decimal(a).to_f64(), Value::Rational(b).to_f64()),
+            },
+            (a, b) => Pair::Floats(a.to_f64(), b.to_f64()),
+        }
+    }
+
+    fn into_floats(self) -> (f64, f64) {
+        match self {
+            Pair::Ints(a, b) => (Value::Int(a).to_f64(), Value::Int(b).to_f64()),
+            Pair::Floats(a, b) => (a, b),
+            Pair::Decimals(a, b) => (Value::Decimal(a).to_f64(), Value::Decimal(b).to_f64()),
+            Pair::Rationals(a, b) => (Value::Rational(a).to_f64(), Value::Rational(b).to_f64()),
+            Pair::Complexes(a, b) => (Value::Complex(a).to_f64(), Value::Complex(b).to_f64()),
+        }
+    }
+}
+
+impl Value {
+    /// Parses a number, representing it according to the mode.
+    /// Whole numbers always become integers, regardless of the mode,
+    /// while numbers that have no exact representation, like "inf", become floats.
+    /// In rational mode, fractions such as "1/3" are numbers too.
+    /// In decimal and rational mode, numbers with an exponent beyond MAX_EXPONENT either way,
+    /// like "1e99999", aren't.
+    /// Lists of numbers are written between brackets, as in "[1 2 3]",
+    /// and matrices as a list of rows, as in "[[1 2][3 4]]".
+    /// Numbers can be followed by a unit after an underscore, as in "300_m" or "9.8_m/s^2".
+    pub fn parse(input: &str, mode: NumMode) -> Option<Value> {
+        if input.starts_with("[[") {
+            return parse_matrix(input, mode);
+        }
+        if input.starts_with('[') {
+            return parse_list(input, mode);
+        }
+        if let Some((number, unit)) = input.split_once('_') {
+            let number = Value::parse(number, mode).filter(|x| x.is_scalar() && !x.has_unit())?;
+            return Some(Value::quantity(number, Unit::parse(unit)?));
+        }
+        if let Some(n) = parse_integer(input) {
+            return Some(Value::Int(n));
+        }
+
+        let exact = match mode {
+            NumMode::Float => None,
+            NumMode::Decimal => match BigDecimal::from_str(input) {
+                Ok(x) if !exponent_fits(&x) => return None,
+                Ok(x) => Some(Value::Decimal(x)),
+                Err(_) => None,
+            },
+            NumMode::Rational => match BigDecimal::from_str(input) {
+                Ok(x) => Some(Value::Rational(decimal_to_rational(&x)?)),
+                Err(_) => parse_fraction(input).map(Value::Rational),
+            },
+        };
+
+        exact
+            .or_else(|| input.parse().ok().map(Value::Float))
+            .or_else(|| parse_complex(input).map(Value::Complex))
+    }
+
+    /// Wraps a complex number, which becomes a float if it has no imaginary part
+    pub fn from_complex(z: Complex64) -> Value {
+        if z.im == 0.0 {
+            Value::Float(z.re)
+        } else {
+            Value::Complex(z)
+        }
+    }
+
+    /// Wraps a fraction, which becomes an integer if it is whole
+    pub fn from_rational(x: BigRational) -> Value {
+        if x.is_integer() {
+            Value::Int(x.to_integer())
+        } else {
+            Value::Rational(x)
+        }
+    }
+
+    /// Measures a number in a unit, leaving it a plain number if the unit is none
+    pub fn quantity(x: Value, unit: Unit) -> Value {
+        if unit.is_none() {
+            x
+        } else {
+            Value::Quantity(Box::new(x), unit)
+        }
+    }
+
+    /// Splits a number into its plain value and its unit, which is none for plain numbers
+    pub fn into_parts(self) -> (Value, Unit) {
+        match self {
+            Value::Quantity(x, unit) => (*x, unit),
+            x => (x, Unit::none()),
+        }
+    }
+
+    /// The unit the value is measured in, which is none for plain numbers
+    pub fn unit(&self) -> Unit {
+        match *self {
+            Value::Quantity(_, ref unit) => unit.clone(),
+            _ => Unit::none(),
+        }
+    }
+
+    pub fn has_unit(&self) -> bool {
+        matches!(*self, Value::Quantity(..))
+    }
+
+    /// Represents the result of an operation on exact numbers that may not be whole,
+    /// such as 1 / 3, according to the mode
+    pub fn inexact(x: BigRational, mode: NumMode, precision: u64) -> Value {
+        match mode {
+            _ if x.is_integer() => Value::Int(x.to_integer()),
+            NumMode::Float => Value::Float(x.to_f64().unwrap_or(f64::NAN)),
+            NumMode::Decimal => Value::Decimal(rational_to_decimal(&x, precision)),
+            NumMode::Rational => Value::Rational(x),
+        }
+    }
+
+    /// Converts the value to a float, losing precision if need be.
+    /// Complex numbers with an imaginary part become NaN, and so do numbers with a unit,
+    /// so operations that don't know about units don't silently drop them.
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            Value::Int(ref n) => n.to_f64().unwrap_or(f64::NAN),
+            Value::Float(x) => x,
+            Value::Complex(z) if z.im == 0.0 => z.re,
+            Value::Complex(_) | Value::List(_) | Value::Matrix(_) | Value::Quantity(..) => f64::NAN,
+            Value::Decimal(ref x) => x.to_f64().unwrap_or(f64::NAN),
+            Value::Rational(ref x) => x.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    /// Converts the value to a complex number
+    pub fn to_complex(&self) -> Complex64 {
+        match *self {
+            Value::Complex(z) => z,
+            ref value => Complex64::new(value.to_f64(), 0.0),
+        }
+    }
+
+    pub fn is_complex(&self) -> bool {
+        matches!(*self, Value::Complex(_))
+    }
+
+    /// Converts the value to an integer, if it is a whole number
+    pub fn to_integer(&self) -> Option<BigInt> {
+        match *self {
+            Value::Int(ref n) => Some(n.clone()),
+            Value::Float(x) if x.fract() == 0.0 => BigInt::from_f64(x),
+            Value::Decimal(ref x) if x.is_integer() => x.with_scale(0).as_bigint_and_exponent().0.into(),
+            Value::Rational(ref x) if x.is_integer() => Some(x.to_integer()),
+            Value::Complex(z) if z.im == 0.0 => Value::Float(z.re).to_integer(),
+            _ => None,
+        }
+    }
+
+    /// Converts the value to an exact fraction.
+    /// Floats become the simplest fraction that rounds to them,
+    /// so 0.1 becomes 1/10 rather than 3602879701896397/36028797018963968.
+    /// Returns None for infinities and NaN, and for decimals with an exponent beyond MAX_EXPONENT.
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match *self {
+            Value::Int(ref n) => Some(BigRational::from_integer(n.clone())),
+            Value::Float(x) => float_to_rational(x),
+            Value::Decimal(ref x) => decimal_to_rational(x),
+            Value::Rational(ref x) => Some(x.clone()),
+            Value::Complex(_) | Value::List(_) | Value::Matrix(_) | Value::Quantity(..) => None,
+        }
+    }
+
+    pub fn is_list(&self) -> bool {
+        matches!(*self, Value::List(_))
+    }
+
+    /// Whether the value is a single number, rather than a list or a matrix
+    pub fn is_scalar(&self) -> bool {
+        !matches!(*self, Value::List(_) | Value::Matrix(_))
+    }
+
+    /// Whether the value is NaN, or a list or matrix with NaN in it
+    pub fn is_nan(&self) -> bool {
+        match *self {
+            Value::Float(x) => x.is_nan(),
+            Value::Complex(z) => z.is_nan(),
+            Value::Int(_) | Value::Decimal(_) | Value::Rational(_) => false,
+            Value::List(ref xs) => xs.iter().any(Value::is_nan),
+            Value::Matrix(ref m) => m.elements().any(Value::is_nan),
+            Value::Quantity(ref x, _) => x.is_nan(),
+        }
+    }
+
+    /// Whether the value is zero, or a list or matrix with zero in it,
+    /// since dividing by such a list divides by zero
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            Value::Int(ref n) => n.is_zero(),
+            Value::Float(x) => x == 0.0,
+            Value::Decimal(ref x) => x.is_zero(),
+            Value::Rational(ref x) => x.is_zero(),
+            Value::Complex(z) => z.is_zero(),
+            Value::List(ref xs) => xs.iter().any(Value::is_zero),
+            Value::Matrix(ref m) => m.elements().any(Value::is_zero),
+            Value::Quantity(ref x, _) => x.is_zero(),
+        }
+    }
+
+    /// Applies a function to every element of a list or matrix, or to the value itself if it isn't one
+    pub fn map<F>(self, fun: &F) -> Value
+    where
+        F: Fn(Value) -> Value,
+    {
+        match self {
+            Value::List(xs) => Value::List(xs.into_iter().map(|x| x.map(fun)).collect()),
+            Value::Matrix(m) => Value::Matrix(m.map(fun)),
+            value => fun(value),
+        }
+    }
+
+    /// The dimensions of the value: the length of a list, the rows and columns of a matrix,
+    /// or none for a single number
+    pub fn shape(&self) -> Vec<usize> {
+        match *self {
+            Value::List(ref xs) => vec![xs.len()],
+            Value::Matrix(ref m) => vec![m.rows(), m.cols()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether two values can be combined element-wise, which they can
+    /// if either is a single number, or if they're lists or matrices of the same shape
+    pub fn fits(&self, other: &Value) -> bool {
+        self.is_scalar() || other.is_scalar() || self.shape() == other.shape()
+    }
+
+    /// Whether two values can be multiplied: a matrix needs as many columns as
+    /// the rows of a matrix on its right, or the elements of a list on its right,
+    /// and a list needs as many elements as the rows of a matrix on its right.
+    /// Anything else is multiplied element-wise, so it has to fit.
+    pub fn fits_product(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Matrix(a), Value::Matrix(b)) => a.cols() == b.rows(),
+            (Value::Matrix(a), Value::List(xs)) => a.cols() == xs.len(),
+            (Value::List(xs), Value::Matrix(b)) => xs.len() == b.rows(),
+            (a, b) => a.fits(b),
+        }
+    }
+
+    /// Divides by `other`, rounding inexact decimal results to `precision` significant digits.
+    /// Integers that don't divide evenly give a result according to the mode.
+    /// Dividing by zero is left to the caller to prevent.
+    /// Units with powers that grow too big give NaN.
+    /// A matrix can't be divided by another, which gives NaN too, rather than dividing element-wise.
+    pub fn div(self, other: Value, mode: NumMode, precision: u64) -> Value {
+        if let (&Value::Matrix(_), &Value::Matrix(_)) = (&self, &other) {
+            return Value::Float(f64::NAN);
+        }
+        if !self.is_scalar() || !other.is_scalar() {
+            return broadcast(self, other, |a, b| a.div(b, mode, precision));
+        }
+        if self.has_unit() || other.has_unit() {
+            let ((x, u), (y, v)) = (self.into_parts(), other.into_parts());
+            return match u.times(&v, -1) {
+                Some(unit) => Value::quantity(x.div(y, mode, precision), unit),
+                None => Value::Float(f64::NAN),
+            };
+        }
+        match Pair::new(self, other) {
+            Pair::Ints(a, b) => Value::inexact(BigRational::new(a, b), mode, precision),
+            Pair::Floats(a, b) => Value::Float(a / b),
+            Pair::Decimals(a, b) => Value::Decimal(decimal_div(&a, &b, precision)),
+            Pair::Rationals(a, b) => Value::from_rational(a / b),
+            Pair::Complexes(a, b) => Value::from_complex(a / b),
+        }
+    }
+
+    /// Raises to the power of `other`.
+    /// Integers raised to a whole number stay exact, with negative powers giving a result
+    /// according to the mode. Decimals raised to a whole number stay exact,
+    /// up to the precision for negative powers, and fractions raised to a whole number stay exact.
+    /// Real numbers with no real power, like -8 ^ 0.5, are raised in the complex plane.
+    /// Matrices have no power, which gives NaN.
+    /// Units are raised along with their number, as long as the exponent has no unit
+    /// and leaves every part of the unit with a whole power.
+    pub fn pow(self, other: Value, mode: NumMode, precision: u64) -> Value {
+        if let (&Value::Matrix(_), _) | (_, &Value::Matrix(_)) = (&self, &other) {
+            return Value::Float(f64::NAN);
+        }
+        if self.is_list() || other.is_list() {
+            return broadcast(self, other, |a, b| a.pow(b, mode, precision));
+        }
+        if self.has_unit() || other.has_unit() {
+            let unit = match other.has_unit() {
+                false => self.unit().pow(other.to_f64()),
+                true => None,
+            };
+            return match unit {
+                Some(unit) => Value::quantity(self.into_parts().0.pow(other, mode, precision), unit),
+                None => Value::Float(f64::NAN),
+            };
+        }
+        match Pair::new(self, other) {
+            Pair::Ints(ref a, ref b)
+                if b.to_i32().is_some() && !(a.is_zero() && b.is_negative()) && int_power_fits(a, b) =>
+            {
+                let power = BigRational::from_integer(a.clone()).pow(b.to_i32().unwrap());
+                Value::inexact(power, mode, precision)
+            }
+            Pair::Complexes(a, b) => match whole_exponent(b) {
+                Some(exp) => Value::from_complex(a.powi(exp)),
+                None => Value::from_complex(a.powc(b)),
+            },
+            Pair::Rationals(ref a, ref b)
+                if b.is_integer()
+                    && b.to_integer().to_i32().is_some()
+                    && !(a.is_zero() && b.is_negative())
+                    && rational_power_fits(a, &b.to_integer()) =>
+            {
+                Value::from_rational(a.pow(b.to_integer().to_i32().unwrap()))
+            }
+            Pair::Decimals(ref a, ref b) if b.is_integer() && b.abs().to_u32().is_some() => {
+                let exp = b.abs().to_u32().unwrap();
+                let result = decimal_powu(a, exp, precision);
+                if b.is_negative() {
+                    Value::Decimal(decimal_div(&BigDecimal::from(1), &result, precision))
+                } else {
+                    Value::Decimal(result).round_to(precision)
+                }
+            }
+            pair => {
+                let (a, b) = pair.into_floats();
+                let result = a.powf(b);
+                if result.is_nan() && !a.is_nan() && !b.is_nan() {
+                    Value::from_complex(Complex64::new(a, 0.0).powc(Complex64::new(b, 0.0)))
+                } else {
+                    Value::Float(result)
+                }
+            }
+        }
+    }
+
+    /// Rounds decimals to `precision` significant digits, leaving other values alone
+    pub fn round_to(self, precision: u64) -> Value {
+        match self {
+            Value::Decimal(x) => Value::Decimal(x.with_prec(precision).normalized()),
+            Value::List(xs) => Value::List(xs.into_iter().map(|x| x.round_to(precision)).collect()),
+            Value::Matrix(m) => Value::Matrix(m.map(|x| x.round_to(precision))),
+            Value::Quantity(x, unit) => Value::Quantity(Box::new(x.round_to(precision)), unit),
+            value => value,
+        }
+    }
+
+    pub fn abs(self) -> Value {
+        match self {
+            Value::Int(n) => Value::Int(n.abs()),
+            Value::Float(x) => Value::Float(x.abs()),
+            Value::Decimal(x) => Value::Decimal(x.abs()),
+            Value::Rational(x) => Value::Rational(x.abs()),
+            Value::Complex(z) => Value::Float(z.norm()),
+            Value::Quantity(x, unit) => Value::Quantity(Box::new(x.abs()), unit),
+            many => many.map(&Value::abs),
+        }
+    }
+
+    /// Compares two real numbers, or returns None if either is complex, NaN, a list or a matrix.
+    /// Numbers with units can only be compared with numbers in the same unit.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        if !self.is_scalar() || !other.is_scalar() {
+            return None;
+        }
+        if self.has_unit() || other.has_unit() {
+            return match (self, other) {
+                (Value::Quantity(x, u), Value::Quantity(y, v)) if u == v => x.compare(y),
+                _ => None,
+            };
+        }
+        match Pair::new(self.clone(), other.clone()) {
+            Pair::Ints(a, b) => Some(a.cmp(&b)),
+            Pair::Floats(a, b) => a.partial_cmp(&b),
+            Pair::Decimals(a, b) => Some(a.cmp(&b)),
+            Pair::Rationals(a, b) => Some(a.cmp(&b)),
+            Pair::Complexes(..) => None,
+        }
+    }
+}
+
+/// Applies a binary operation element-wise to operands of which at least one is a list or matrix,
+/// pairing a single number with every element of the list or matrix.
+/// Operands of different shapes give NaN, which callers are expected to prevent with `fits`.
+fn broadcast<F>(a: Value, b: Value, fun: F) -> Value
+where
+    F: Fn(Value, Value) -> Value,
+{
+    if !a.fits(&b) {
+        return Value::Float(f64::NAN);
+    }
+    match (a, b) {
+        (Value::Matrix(a), Value::Matrix(b)) => Value::Matrix(a.zip_with(b, fun)),
+        (Value::Matrix(a), b) => Value::Matrix(a.map(|x| fun(x, b.clone()))),
+        (a, Value::Matrix(b)) => Value::Matrix(b.map(|y| fun(a.clone(), y))),
+        (Value::List(xs), Value::List(ys)) => Value::List(xs.into_iter().zip(ys).map(|(x, y)| fun(x, y)).collect()),
+        (Value::List(xs), b) => Value::List(xs.into_iter().map(|x| fun(x, b.clone())).collect()),
+        (a, Value::List(ys)) => Value::List(ys.into_iter().map(|y| fun(a.clone(), y)).collect()),
+        (a, b) => fun(a, b),
+    }
+}
+
+/// Adds or subtracts numbers measured in the same unit, keeping the unit.
+/// Different units give NaN, which callers are expected to prevent by converting them first.
+fn same_unit<F>(a: Value, b: Value, fun: F) -> Value
+where
+    F: Fn(Value, Value) -> Value,
+{
+    let ((x, u), (y, v)) = (a.into_parts(), b.into_parts());
+    if u == v {
+        Value::quantity(fun(x, y), u)
+    } else {
+        Value::Float(f64::NAN)
+    }
+}
+
+/// The exponent as an integer, if it is a real whole number that fits,
+/// so integer powers of complex numbers don't pick up rounding errors
+fn whole_exponent(exp: Complex64) -> Option<i32> {
+    if exp.im == 0.0 && exp.re.fract() == 0.0 && exp.re.abs() <= i32::MAX as f64 {
+        Some(exp.re as i32)
+    } else {
+        None
+    }
+}
+
+/// Parses a complex number such as "3+4i", "2i", "-i" or "1.5e-3-2i"
+fn parse_complex(input: &str) -> Option<Complex64> {
+    let body = input.strip_suffix('i')?;
+
+    // The imaginary part starts at the last sign that neither leads the number nor belongs to an exponent
+    let split = body
+        .char_indices()
+        .rev()
+        .find(|&(i, c)| (c == '+' || c == '-') && i > 0 && !body[..i].ends_with(['e', 'E']))
+        .map_or(0, |(i, _)| i);
+    let (re, im) = body.split_at(split);
+
+    let re = if re.is_empty() { 0.0 } else { re.parse().ok()? };
+    let im = match im {
+        "" | "+" => 1.0,
+        "-" => -1.0,
+        im => im.parse().ok()?,
+    };
+
+    Some(Complex64::new(re, im))
+}
+
+/// Parses a list of numbers such as "[1 2 3]", separated by whitespace or commas.
+/// Lists can't hold other lists.
+fn parse_list(input: &str, mode: NumMode) -> Option<Value> {
+    let body = input.strip_prefix('[')?.strip_suffix(']')?;
+    let xs = body
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|x| !x.is_empty())
+        .map(|x| Value::parse(x, mode).filter(Value::is_scalar))
+        .collect::<Option<Vec<Value>>>()?;
+    Some(Value::List(xs))
+}
+
+/// Parses a matrix such as "[[1 2][3 4]]", whose rows are lists of the same length,
+/// optionally separated by whitespace or commas
+fn parse_matrix(input: &str, mode: NumMode) -> Option<Value> {
+    let mut rest = input.strip_prefix('[')?.strip_suffix(']')?;
+    let mut rows = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(']')? + 1;
+        match parse_list(&rest[..end], mode)? {
+            Value::List(xs) => rows.push(xs),
+            _ => return None,
+        }
+        rest = &rest[end..];
+    }
+
+    Matrix::new(rows).map(Value::Matrix)
+}
+
+/// Parses a whole number such as "42", "-7", "0x1f", "0o17" or "0b1010", but not "1e3" or "2.0"
+fn parse_integer(input: &str) -> Option<BigInt> {
+    let (negative, unsigned) = match input.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, input.strip_prefix('+').unwrap_or(input)),
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x") => (16, &unsigned[2..]),
+        Some("0o") => (8, &unsigned[2..]),
+        Some("0b") => (2, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+
+    let n = BigInt::parse_bytes(digits.as_bytes(), radix)?;
+    Some(if negative { -n } else { n })
+}
+
+/// Parses a fraction such as "1/3" or "-2/4"
+fn parse_fraction(input: &str) -> Option<BigRational> {
+    let (numer, denom) = input.split_once('/')?;
+    let numer = BigInt::from_str(numer).ok()?;
+    let denom = BigInt::from_str(denom).ok()?;
+
+    if denom.is_zero() {
+        None
+    } else {
+        Some(BigRational::new(numer, denom))
+    }
+}
+
+/// Finds the simplest fraction that rounds to the float,
+/// by walking the convergents of its continued fraction
+fn float_to_rational(x: f64) -> Option<BigRational> {
+    let mut rest = BigRational::from_float(x)?;
+    let (mut numer, mut prev_numer) = (BigInt::one(), BigInt::zero());
+    let (mut denom, mut prev_denom) = (BigInt::zero(), BigInt::one());
+
+    loop {
+        let whole = rest.floor();
+        let term = whole.to_integer();
+        let next_numer = &term * &numer + &prev_numer;
+        let next_denom = &term * &denom + &prev_denom;
+        let convergent = BigRational::new(next_numer.clone(), next_denom.clone());
+
+        let fraction = rest - whole;
+        if fraction.is_zero() || convergent.to_f64() == Some(x) {
+            return Some(convergent);
+        }
+
+        rest = fraction.recip();
+        prev_numer = mem::replace(&mut numer, next_numer);
+        prev_denom = mem::replace(&mut denom, next_denom);
+    }
+}
+
+/// Whether a decimal has its point no further than MAX_EXPONENT from its digits
+fn exponent_fits(x: &BigDecimal) -> bool {
+    x.as_bigint_and_exponent().1.abs() <= MAX_EXPONENT
+}
+
+/// Converts a decimal to the exact fraction it represents,
+/// unless its exponent is beyond MAX_EXPONENT either way, which would make the fraction huge
+fn decimal_to_rational(x: &BigDecimal) -> Option<BigRational> {
+    if !exponent_fits(x) {
+        return None;
+    }
+    let (digits, scale) = x.as_bigint_and_exponent();
+    let ten = BigInt::from(10);
+
+    if scale >= 0 {
+        Some(BigRational::new(digits, ten.pow(scale as u32)))
+    } else {
+        Some(BigRational::from_integer(digits * ten.pow(-scale as u32)))
+    }
+}
+
+/// Converts a fraction to a decimal, rounded to `precision` significant digits
+pub fn rational_to_decimal(x: &BigRational, precision: u64) -> BigDecimal {
+    decimal_div(&BigDecimal::from(x.numer().clone()), &BigDecimal::from(x.denom().clone()), precision)
+}
+
+/// Divides two decimals, rounding the result to `precision` significant digits
+fn decimal_div(a: &BigDecimal, b: &BigDecimal, precision: u64) -> BigDecimal {
+    let (a_digits, a_scale) = a.as_bigint_and_exponent();
+    let (b_digits, b_scale) = b.as_bigint_and_exponent();
+
+    // Shift the dividend far enough to the left that the quotient
+    // has at least one more digit than needed, so it can be rounded properly.
+    let shift = (precision as i64 + b.digits() as i64 - a.digits() as i64 + 1).max(0);
+    let quotient = a_digits * BigInt::from(10).pow(shift as u32) / b_digits;
+
+    BigDecimal::new(quotient, a_scale - b_scale + shift)
+        .with_prec(precision)
+        .normalized()
+}
+
+/// Whether an exact integer power is small enough to work out.
+/// Powers of 0, 1 and -1 never grow, whatever the exponent.
+fn int_power_fits(base: &BigInt, exp: &BigInt) -> bool {
+    let bits = if base.abs() <= BigInt::one() { 0 } else { base.bits() };
+    exp.abs().to_u64().is_some_and(|exp| integer::power_fits(bits, exp))
+}
+
+/// Whether an exact rational power is small enough to work out,
+/// going by the bigger of the numerator and the denominator
+fn rational_power_fits(base: &BigRational, exp: &BigInt) -> bool {
+    let bits = if base.is_zero() || base.abs().is_one() { 0 } else { base.numer().bits().max(base.denom().bits()) };
+    exp.abs().to_u64().is_some_and(|exp| integer::power_fits(bits, exp))
+}
+
+/// Raises a decimal to a whole power by repeated squaring.
+/// Every step is rounded to a few digits past the precision, so big exponents don't blow up.
+fn decimal_powu(base: &BigDecimal, mut exp: u32, precision: u64) -> BigDecimal {
+    let digits = precision + GUARD_DIGITS;
+    let mut base = base.clone();
+    let mut result = BigDecimal::from(1);
+
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = (&result * &base).with_prec(digits);
+        }
+        base = base.square().with_prec(digits);
+        exp >>= 1;
+    }
+
+    result
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(BigInt::from(n))
+    }
+}
+
+impl From<BigInt> for Value {
+    fn from(n: BigInt) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<BigDecimal> for Value {
+    fn from(x: BigDecimal) -> Self {
+        Value::Decimal(x)
+    }
+}
+
+impl From<BigRational> for Value {
+    fn from(x: BigRational) -> Self {
+        Value::Rational(x)
+    }
+}
+
+impl From<Complex64> for Value {
+    fn from(z: Complex64) -> Self {
+        Value::from_complex(z)
+    }
+}
+
+impl Add for Value {
+    type Output = Value;
+
+    fn add(self, other: Value) -> Value {
+        if !self.is_scalar() || !other.is_scalar() {
+            return broadcast(self, other, Value::add);
+        }
+        if self.has_unit() || other.has_unit() {
+            return same_unit(self, other, Value::add);
+        }
+        match Pair::new(self, other) {
+            Pair::Ints(a, b) => Value::Int(a + b),
+            Pair::Floats(a, b) => Value::Float(a + b),
+            Pair::Decimals(a, b) => Value::Decimal(a + b),
+            Pair::Rationals(a, b) => Value::from_rational(a + b),
+            Pair::Complexes(a, b) => Value::from_complex(a + b),
+        }
+    }
+}
+
+impl Sub for Value {
+    type Output = Value;
+
+    fn sub(self, other: Value) -> Value {
+        if !self.is_scalar() || !other.is_scalar() {
+            return broadcast(self, other, Value::sub);
+        }
+        if self.has_unit() || other.has_unit() {
+            return same_unit(self, other, Value::sub);
+        }
+        match Pair::new(self, other) {
+            Pair::Ints(a, b) => Value::Int(a - b),
+            Pair::Floats(a, b) => Value::Float(a - b),
+            Pair::Decimals(a, b) => Value::Decimal(a - b),
+            Pair::Rationals(a, b) => Value::from_rational(a - b),
+            Pair::Complexes(a, b) => Value::from_complex(a - b),
+        }
+    }
+}
+
+impl Mul for Value {
+    type Output = Value;
+
+    /// Multiplies matrices by matrices and lists as in linear algebra, where a list on the right
+    /// is a column and a list on the left a row. Anything else is multiplied element-wise.
+    /// Operands that don't fit give NaN, which callers are expected to prevent with `fits_product`,
+    /// as do units with powers that grow too big.
+    fn mul(self, other: Value) -> Value {
+        if !self.fits_product(&other) {
+            return Value::Float(f64::NAN);
+        }
+        match (&self, &other) {
+            (Value::Matrix(a), Value::Matrix(b)) => return Value::Matrix(a.product(b)),
+            (Value::Matrix(a), Value::List(xs)) => return Value::List(a.apply(xs)),
+            (Value::List(xs), Value::Matrix(b)) => return Value::List(b.apply_left(xs)),
+            _ => (),
+        }
+        if !self.is_scalar() || !other.is_scalar() {
+            return broadcast(self, other, Value::mul);
+        }
+        if self.has_unit() || other.has_unit() {
+            let ((x, u), (y, v)) = (self.into_parts(), other.into_parts());
+            return match u.times(&v, 1) {
+                Some(unit) => Value::quantity(x.mul(y), unit),
+                None => Value::Float(f64::NAN),
+            };
+        }
+        match Pair::new(self, other) {
+            Pair::Ints(a, b) => Value::Int(a * b),
+            Pair::Floats(a, b) => Value::Float(a * b),
+            Pair::Decimals(a, b) => Value::Decimal(a * b),
+            Pair::Rationals(a, b) => Value::from_rational(a * b),
+            Pair::Complexes(a, b) => Value::from_complex(a * b),
+        }
+    }
+}
+
+impl Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        match self {
+            Value::Int(n) => Value::Int(-n),
+            Value::Float(x) => Value::Float(-x),
+            Value::Decimal(x) => Value::Decimal(-x),
+            Value::Rational(x) => Value::Rational(-x),
+            Value::Complex(z) => Value::Complex(-z),
+            Value::Quantity(x, unit) => Value::Quantity(Box::new(-*x), unit),
+            many => many.map(&Value::neg),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Int(ref n) => n.fmt(f),
+            Value::Float(x) => x.fmt(f),
+            Value::Decimal(ref x) => x.normalized().fmt(f),
+            Value::Rational(ref x) => x.fmt(f),
+            Value::Complex(z) => {
+                // honour the precision for both parts, as in {:.2}
+                let precision = f.precision();
+                let part = |x: f64| match precision {
+                    Some(precision) => format!("{:.*}", precision, x),
+                    None => x.to_string(),
+                };
+                write!(f, "{}", format_complex(z, part))
+            }
+            Value::List(ref xs) => {
+                let xs: Vec<String> = xs.iter().map(|x| x.to_string()).collect();
+                write!(f, "[{}]", xs.join(" "))
+            }
+            Value::Matrix(ref m) => m.fmt(f),
+            Value::Quantity(ref x, ref unit) => {
+                x.fmt(f)?;
+                write!(f, "_{}", unit)
+            }
+        }
+    }
+}
+
+/// Writes a complex number as re+imi, with both parts formatted by `part`.
+/// Purely imaginary numbers leave out the real part, as in 2i.
+pub fn format_complex<F>(z: Complex64, part: F) -> String
+where
+    F: Fn(f64) -> String,
+{
+    if z.re == 0.0 {
+        format!("{}i", part(z.im))
+    } else if z.im.is_sign_negative() {
+        format!("{}-{}i", part(z.re), part(-z.im))
+    } else {
+        format!("{}+{}i", part(z.re), part(z.im))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str, mode: NumMode) -> Value {
+        Value::parse(input, mode).unwrap()
+    }
+
+    fn decimal(input: &str) -> BigDecimal {
+        BigDecimal::from_str(input).unwrap()
+    }
+
+    fn rational(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(BigInt::from(numer), BigInt::from(denom))
+    }
+
+    #[test]
+    fn parses_numbers_according_to_the_mode() {
+        assert_eq!(parse("42", NumMode::Float), Value::from(42));
+        assert_eq!(parse("0x1f", NumMode::Float), Value::from(31));
+        assert_eq!(parse("-0b101", NumMode::Float), Value::from(-5));
+        assert_eq!(parse("2.5", NumMode::Float), Value::Float(2.5));
+        assert_eq!(parse("2.5", NumMode::Decimal), Value::Decimal(decimal("2.5")));
+        assert_eq!(parse("2.5", NumMode::Rational), Value::Rational(rational(5, 2)));
+        assert_eq!(parse("-2/4", NumMode::Rational), Value::Rational(rational(-1, 2)));
+        assert_eq!(parse("inf", NumMode::Decimal), Value::Float(f64::INFINITY));
+        assert_eq!(Value::parse("1/3", NumMode::Float), None);
+        assert_eq!(Value::parse("1/0", NumMode::Rational), None);
+        assert_eq!(Value::parse("0x", NumMode::Float), None);
+        assert_eq!(Value::parse("abc", NumMode::Float), None);
+    }
+
+    #[test]
+    fn parses_complex_numbers() {
+        assert_eq!(parse_complex("3+4i"), Some(Complex64::new(3.0, 4.0)));
+        assert_eq!(parse_complex("2i"), Some(Complex64::new(0.0, 2.0)));
+        assert_eq!(parse_complex("-i"), Some(Complex64::new(0.0, -1.0)));
+        assert_eq!(parse_complex("i"), Some(Complex64::new(0.0, 1.0)));
+        assert_eq!(parse_complex("1.5e-3-2i"), Some(Complex64::new(1.5e-3, -2.0)));
+        assert_eq!(parse_complex("1e+2+1e-1i"), Some(Complex64::new(100.0, 0.1)));
+        assert_eq!(parse_complex("3+4"), None);
+        assert_eq!(parse_complex("x+4i"), None);
+    }
+
+    #[test]
+    fn parses_lists() {
+        let list = Value::List(vec![Value::from(1), Value::from(2), Value::from(3)]);
+        assert_eq!(parse("[1 2 3]", NumMode::Float), list);
+        assert_eq!(parse("[1,2, 3]", NumMode::Float), list);
+        assert_eq!(parse("[]", NumMode::Float), Value::List(Vec::new()));
+        assert_eq!(Value::parse("[1 [2]]", NumMode::Float), None);
+        assert_eq!(list.to_string(), "[1 2 3]");
+    }
+
+    #[test]
+    fn parses_matrices() {
+        assert_eq!(parse("[[1 2][3 4]]", NumMode::Float).shape(), vec![2, 2]);
+        assert_eq!(parse("[[1 2], [3 4], [5 6]]", NumMode::Float).shape(), vec![3, 2]);
+        assert_eq!(Value::parse("[[1 2][3]]", NumMode::Float), None);
+        assert_eq!(Value::parse("[[]]", NumMode::Float), None);
+        assert_eq!(parse("[[1 2][3 4]]", NumMode::Float).to_string(), "[[1 2][3 4]]");
+    }
+
+    #[test]
+    fn parses_quantities() {
+        let speed = parse("3_m/s", NumMode::Float);
+        assert_eq!(speed.unit(), Unit::parse("m/s").unwrap());
+        assert_eq!(speed.into_parts().0, Value::from(3));
+        assert_eq!(Value::parse("3_parsec", NumMode::Float), None);
+        assert_eq!(Value::parse("[1 2]_m", NumMode::Float), None);
+        assert_eq!(parse("9.5_m/s^2", NumMode::Float).to_string(), "9.5_m/s^2");
+    }
+
+    #[test]
+    fn only_parses_fractions_with_a_reasonable_exponent() {
+        assert_eq!(parse("2.5e-3", NumMode::Rational), Value::Rational(rational(1, 400)));
+        assert_eq!(Value::parse("1e999999999", NumMode::Rational), None);
+        assert_eq!(Value::parse("1e-999999999", NumMode::Rational), None);
+
+        let huge = Value::Decimal(BigDecimal::new(BigInt::from(1), -999_999_999));
+        assert_eq!(huge.to_rational(), None);
+        assert_eq!(Value::Rational(rational(1, 2)).add(huge), Value::Float(f64::INFINITY));
+    }
+
+    #[test]
+    fn finds_the_simplest_fraction_for_a_float() {
+        assert_eq!(float_to_rational(0.1), Some(rational(1, 10)));
+        assert_eq!(float_to_rational(-0.75), Some(rational(-3, 4)));
+        assert_eq!(float_to_rational(1.0 / 3.0), Some(rational(1, 3)));
+        assert_eq!(float_to_rational(5.0), Some(rational(5, 1)));
+        assert_eq!(float_to_rational(f64::NAN), None);
+        assert_eq!(float_to_rational(f64::INFINITY), None);
+    }
+
+    #[test]
+    fn only_parses_decimals_with_a_reasonable_exponent() {
+        assert_eq!(Value::parse("1.5e3", NumMode::Decimal), Some(Value::Decimal(decimal("1500"))));
+        assert!(Value::parse("1e10000", NumMode::Decimal).is_some());
+        assert!(Value::parse("1e-10000", NumMode::Decimal).is_some());
+        assert_eq!(Value::parse("1e999999999", NumMode::Decimal), None);
+        assert_eq!(Value::parse("1e-999999999_m", NumMode::Decimal), None);
+        assert_eq!(Value::parse("1e999999999", NumMode::Float), Some(Value::Float(f64::INFINITY)));
+    }
+
+    #[test]
+    fn divides_decimals_to_the_precision() {
+        assert_eq!(decimal_div(&decimal("1"), &decimal("3"), 5), decimal("0.33333"));
+        assert_eq!(decimal_div(&decimal("2"), &decimal("3"), 5), decimal("0.66667"));
+        assert_eq!(decimal_div(&decimal("100"), &decimal("0.04"), 5), decimal("2500"));
+        assert_eq!(decimal_div(&decimal("-1"), &decimal("8"), 50), decimal("-0.125"));
+        assert_eq!(decimal_div(&decimal("123456789"), &decimal("1"), 3), decimal("123000000"));
+    }
+
+    #[test]
+    fn converts_fractions_according_to_the_mode() {
+        assert_eq!(Value::inexact(rational(6, 3), NumMode::Float, 50), Value::from(2));
+        assert_eq!(Value::inexact(rational(1, 4), NumMode::Float, 50), Value::Float(0.25));
+        assert_eq!(Value::inexact(rational(1, 3), NumMode::Decimal, 3), Value::Decimal(decimal("0.333")));
+        assert_eq!(Value::inexact(rational(1, 3), NumMode::Rational, 3), Value::Rational(rational(1, 3)));
+        assert_eq!(Value::from_rational(rational(4, 2)), Value::from(2));
+        assert_eq!(Value::from_complex(Complex64::new(2.0, 0.0)), Value::Float(2.0));
+    }
+
+    #[test]
+    fn raises_exact_numbers_exactly() {
+        let pow = |a: &str, b: &str, mode| parse(a, mode).pow(parse(b, mode), mode, 50);
+        assert_eq!(pow("2", "100", NumMode::Float), Value::Int(BigInt::from(1) << 100));
+        assert_eq!(pow("2", "-2", NumMode::Float), Value::Float(0.25));
+        assert_eq!(pow("2", "-2", NumMode::Rational), Value::Rational(rational(1, 4)));
+        assert_eq!(pow("2/3", "2", NumMode::Rational), Value::Rational(rational(4, 9)));
+        assert_eq!(pow("1.5", "2", NumMode::Decimal), Value::Decimal(decimal("2.25")));
+        assert_eq!(pow("2", "-1", NumMode::Decimal), Value::Decimal(decimal("0.5")));
+        assert!(pow("-8", "0.5", NumMode::Float).is_complex());
+    }
+
+    #[test]
+    fn rounds_decimal_powers_as_it_goes() {
+        // so they're quick whatever the exponent
+        let big = Value::Decimal(decimal("2.5")).pow(Value::from(1_000_000_000), NumMode::Decimal, 20);
+        match big {
+            Value::Decimal(x) => assert!(x.digits() <= 20),
+            other => panic!("expected a decimal, got {}", other),
+        }
+    }
+
+    #[test]
+    fn gives_up_on_exact_powers_that_are_too_big() {
+        let ten = Value::from(10);
+        assert_eq!(ten.clone().pow(Value::from(1_000_000_000), NumMode::Float, 50), Value::Float(f64::INFINITY));
+        assert_eq!(Value::from(-1).pow(Value::from(1_000_000_001), NumMode::Float, 50), Value::from(-1));
+        assert_eq!(Value::from(1).pow(Value::from(-1_000_000_000), NumMode::Float, 50), Value::from(1));
+
+        let half = Value::Rational(rational(1, 2));
+        assert_eq!(half.pow(Value::from(1_000_000_000), NumMode::Rational, 50), Value::Float(0.0));
+    }
+
+    #[test]
+    fn combines_lists_element_wise() {
+        let xs = parse("[1 2 3]", NumMode::Float);
+        assert_eq!(xs.clone().add(Value::from(1)), parse("[2 3 4]", NumMode::Float));
+        assert_eq!(xs.clone().mul(xs.clone()), parse("[1 4 9]", NumMode::Float));
+        assert!(xs.clone().add(parse("[1 2]", NumMode::Float)).is_nan());
+        assert!(xs.fits(&Value::from(1)));
+        assert!(!parse("[1 2]", NumMode::Float).fits(&parse("[[1 2]]", NumMode::Float)));
+    }
+
+    #[test]
+    fn compares_real_numbers_only() {
+        assert_eq!(Value::from(2).compare(&Value::Float(2.5)), Some(Ordering::Less));
+        assert_eq!(Value::Rational(rational(1, 3)).compare(&Value::Float(0.3)), Some(Ordering::Greater));
+        assert_eq!(Value::Complex(Complex64::new(1.0, 1.0)).compare(&Value::from(1)), None);
+        assert_eq!(Value::Float(f64::NAN).compare(&Value::from(1)), None);
+        assert_eq!(parse("[1]", NumMode::Float).compare(&Value::from(1)), None);
+    }
+
+    #[test]
+    fn compares_quantities_with_units_of_the_same_kind() {
+        assert_eq!(parse("1_m", NumMode::Float).compare(&parse("2_m", NumMode::Float)), Some(Ordering::Less));
+        assert_eq!(parse("1_m", NumMode::Float).compare(&Value::from(2)), None);
+    }
+
+    #[test]
+    fn displays_values() {
+        assert_eq!(Value::Rational(rational(-1, 3)).to_string(), "-1/3");
+        assert_eq!(Value::Decimal(decimal("1.500")).to_string(), "1.5");
+        assert_eq!(Value::Complex(Complex64::new(3.0, -4.0)).to_string(), "3-4i");
+        assert_eq!(Value::Complex(Complex64::new(0.0, 2.0)).to_string(), "2i");
+    }
+}